@@ -0,0 +1,60 @@
+// Compares processing the same inputs with the accounts kept in a HashMap and in a BTreeMap.
+// Run with `cargo bench --bench accounts_store`
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+use transaction_processor::{process_reader_into, Account, AccountId, AccountStore};
+
+const ROWS: u32 = 500_000;
+const RUNS: u32 = 5;
+
+// Deposits and withdrawals spread over the specified number of clients, either visiting them in
+// order or jumping around the whole id range
+fn input(clients: u32, scattered: bool) -> String {
+    let mut csv = String::from("type,client,tx,amount\n");
+    for tx in 1..=ROWS {
+        let index = tx % clients;
+        let client = if scattered {
+            index.wrapping_mul(40_503) % u32::from(u16::MAX)
+        } else {
+            index
+        };
+        let kind = if tx % 4 == 0 { "withdrawal" } else { "deposit" };
+        writeln!(csv, "{},{},{},1.5", kind, client, tx).unwrap();
+    }
+    csv
+}
+
+// Fastest of a few runs, which is the least affected by whatever else the machine is doing
+fn fastest<S: AccountStore + Default>(input: &str) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            black_box(process_reader_into::<S>(input.as_bytes()));
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    for (clients, scattered) in [
+        (100, false),
+        (10_000, false),
+        (10_000, true),
+        (65_535, true),
+    ] {
+        let input = input(clients, scattered);
+        let hash = fastest::<HashMap<AccountId, Account>>(&input);
+        let btree = fastest::<BTreeMap<AccountId, Account>>(&input);
+        println!(
+            "{} rows, {} {} clients: HashMap {:?}, BTreeMap {:?}",
+            ROWS,
+            clients,
+            if scattered { "scattered" } else { "sequential" },
+            hash,
+            btree
+        );
+    }
+}
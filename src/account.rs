@@ -1,61 +1,304 @@
+use crate::money::reported;
 use rust_decimal::Decimal;
-use serde::{Deserialize, Serialize};
+use serde::de::{self, Unexpected, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashSet;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::sync::{OnceLock, PoisonError, RwLock};
 
-#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
-pub(crate) struct AccountId(pub u16);
+// Clients are identified by their numeric id, or with --string-client-ids by the trimmed text of
+// the client column, in which case `01` and `1` are different clients
+#[derive(Copy, Clone, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum AccountId {
+    Number(u16),
+    Name(ClientName),
+}
+
+impl Display for AccountId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            AccountId::Number(number) => write!(f, "{}", number),
+            AccountId::Name(name) => write!(f, "{}", name.as_str()),
+        }
+    }
+}
+
+// Numeric ids keep the `AccountId(1)` rendering used by the original error messages
+impl Debug for AccountId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            AccountId::Number(number) => write!(f, "AccountId({})", number),
+            AccountId::Name(name) => write!(f, "AccountId({:?})", name.as_str()),
+        }
+    }
+}
+
+// Reads back both forms written by Serialize, a number being a numeric id and a string a name.
+// The client column of the input doesn't go through this, since whether `1` is a number or a name
+// depends on --string-client-ids
+impl<'de> Deserialize<'de> for AccountId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct AccountIdVisitor;
+
+        impl<'de> Visitor<'de> for AccountIdVisitor {
+            type Value = AccountId;
+
+            fn expecting(&self, formatter: &mut Formatter) -> fmt::Result {
+                formatter.write_str("a numeric client id or a client name")
+            }
+
+            fn visit_u64<E: de::Error>(self, number: u64) -> Result<AccountId, E> {
+                u16::try_from(number)
+                    .map(AccountId::Number)
+                    .map_err(|_| E::invalid_value(Unexpected::Unsigned(number), &self))
+            }
+
+            fn visit_i64<E: de::Error>(self, number: i64) -> Result<AccountId, E> {
+                u16::try_from(number)
+                    .map(AccountId::Number)
+                    .map_err(|_| E::invalid_value(Unexpected::Signed(number), &self))
+            }
+
+            fn visit_str<E: de::Error>(self, name: &str) -> Result<AccountId, E> {
+                ClientName::new(name)
+                    .map(AccountId::Name)
+                    .ok_or_else(|| E::invalid_value(Unexpected::Str(name), &self))
+            }
+        }
 
-#[derive(Default, Debug)]
-pub(crate) struct Account {
+        deserializer.deserialize_any(AccountIdVisitor)
+    }
+}
+
+impl Serialize for AccountId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            AccountId::Number(number) => number.serialize(serializer),
+            AccountId::Name(name) => name.as_str().serialize(serializer),
+        }
+    }
+}
+
+// Client names are interned, so account ids stay cheap to copy whatever the length of the names.
+// Every distinct name is kept for the rest of the process, like the accounts it identifies
+#[derive(Copy, Clone, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct ClientName(&'static str);
+
+impl ClientName {
+    pub fn new(name: &str) -> Option<Self> {
+        static NAMES: OnceLock<RwLock<HashSet<&'static str>>> = OnceLock::new();
+        if name.is_empty() {
+            return None;
+        }
+        let names = NAMES.get_or_init(Default::default);
+        let interned = names
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(name)
+            .copied();
+        let interned = interned.unwrap_or_else(|| {
+            let mut names = names.write().unwrap_or_else(PoisonError::into_inner);
+            // Another thread might have interned the name since it was looked up
+            match names.get(name) {
+                Some(interned) => interned,
+                None => {
+                    let interned = Box::leak(name.into());
+                    names.insert(interned);
+                    interned
+                }
+            }
+        });
+        Some(ClientName(interned))
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.0
+    }
+}
+
+impl AccountId {
+    // Parses a client id given on the command line the same way the input column is read
+    pub fn parse(client: &str, string_client_ids: bool) -> Option<Self> {
+        if string_client_ids {
+            ClientName::new(client.trim()).map(AccountId::Name)
+        } else {
+            client.trim().parse().ok().map(AccountId::Number)
+        }
+    }
+}
+
+// A balance change which doesn't fit in a Decimal, in which case the account is left as it was
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct BalanceOverflow;
+
+#[derive(Clone, Default, Debug, PartialEq)]
+pub struct Account {
     available: Decimal,
     held: Decimal,
+    // Portion of the held funds set aside as a rolling reserve of deposits rather than by disputes,
+    // the rest of them being held by open disputes
+    reserved: Decimal,
     locked: bool,
 }
 
+// Unrounded state of an account, used when it needs to be persisted exactly
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub(crate) struct Balances {
+    pub available: Decimal,
+    pub held: Decimal,
+    pub reserved: Decimal,
+    pub locked: bool,
+}
+
 impl Account {
-    pub(crate) fn new() -> Self {
+    // Rebuilds an account from its unrounded balances, as persisted by a checkpoint
+    pub(crate) fn from_balances(balances: Balances) -> Self {
+        let Balances {
+            available,
+            held,
+            reserved,
+            locked,
+        } = balances;
         Account {
-            ..Default::default()
+            available,
+            held,
+            reserved,
+            locked,
         }
     }
 
-    pub fn deposit(&mut self, deposit: Decimal) {
-        self.available += deposit;
+    pub fn deposit(&mut self, deposit: Decimal) -> Result<(), BalanceOverflow> {
+        self.update(deposit, Decimal::ZERO, Decimal::ZERO)
+    }
+
+    pub fn withdraw(&mut self, withdrawed: Decimal) -> Result<(), BalanceOverflow> {
+        self.update(-withdrawed, Decimal::ZERO, Decimal::ZERO)
+    }
+
+    // Moves part of the available funds into held as a reserve, which can later be released
+    pub fn reserve(&mut self, reserved: Decimal) -> Result<(), BalanceOverflow> {
+        self.update(-reserved, reserved, reserved)
+    }
+
+    // Adds funds straight into the reserve, such as interest earned by the held funds
+    pub fn credit_reserve(&mut self, credited: Decimal) -> Result<(), BalanceOverflow> {
+        self.update(Decimal::ZERO, credited, credited)
+    }
+
+    pub fn release_reserve(&mut self, released: Decimal) -> Result<(), BalanceOverflow> {
+        self.update(released, -released, -released)
+    }
+
+    pub fn dispute(&mut self, disputed: Decimal) -> Result<(), BalanceOverflow> {
+        self.update(-disputed, disputed, Decimal::ZERO)
     }
 
-    pub fn withdraw(&mut self, withdrawed: Decimal) {
-        self.available -= withdrawed;
+    pub fn resolve(&mut self, resolved: Decimal) -> Result<(), BalanceOverflow> {
+        self.update(resolved, -resolved, Decimal::ZERO)
     }
 
-    pub fn dispute(&mut self, disputed: Decimal) {
-        self.available -= disputed;
-        self.held += disputed;
+    pub fn chargeback(&mut self, charged_back: Decimal) -> Result<(), BalanceOverflow> {
+        self.update(Decimal::ZERO, -charged_back, Decimal::ZERO)
     }
 
-    pub fn resolve(&mut self, resolved: Decimal) {
-        self.available += resolved;
-        self.held -= resolved;
+    // The funds of a disputed withdrawal are held until the dispute is settled, either kept by the
+    // withdrawal when resolved or credited back into the account by a chargeback
+    pub fn dispute_withdrawal(&mut self, disputed: Decimal) -> Result<(), BalanceOverflow> {
+        self.update(Decimal::ZERO, disputed, Decimal::ZERO)
     }
 
-    pub fn chargeback(&mut self, charged_back: Decimal) {
-        self.held -= charged_back;
+    pub fn resolve_withdrawal(&mut self, resolved: Decimal) -> Result<(), BalanceOverflow> {
+        self.update(Decimal::ZERO, -resolved, Decimal::ZERO)
+    }
+
+    pub fn chargeback_withdrawal(&mut self, charged_back: Decimal) -> Result<(), BalanceOverflow> {
+        self.update(charged_back, -charged_back, Decimal::ZERO)
+    }
+
+    // Freezes the account, which the ledger does after a chargeback unless configured otherwise
+    pub fn lock(&mut self) {
         self.locked = true;
     }
 
+    // Adds the changes to every balance, leaving the account untouched if any of them, or the
+    // total they add up to, would overflow
+    fn update(
+        &mut self,
+        available: Decimal,
+        held: Decimal,
+        reserved: Decimal,
+    ) -> Result<(), BalanceOverflow> {
+        let available = self
+            .available
+            .checked_add(available)
+            .ok_or(BalanceOverflow)?;
+        let held = self.held.checked_add(held).ok_or(BalanceOverflow)?;
+        let reserved = self.reserved.checked_add(reserved).ok_or(BalanceOverflow)?;
+        available.checked_add(held).ok_or(BalanceOverflow)?;
+        self.available = available;
+        self.held = held;
+        self.reserved = reserved;
+        Ok(())
+    }
+
     // Getters to deal with the required decimal precision when generating the output file
 
+    // Derived rather than stored, so disputes and resolves moving funds between available and held
+    // can never change it
     pub fn total(&self) -> Decimal {
-        (self.available + self.held).round_dp(4).normalize()
+        reported(self.available + self.held)
     }
 
     pub fn held(&self) -> Decimal {
-        self.held.round_dp(4).normalize()
+        reported(self.held)
     }
 
     pub fn available(&self) -> Decimal {
-        self.available.round_dp(4).normalize()
+        reported(self.available)
+    }
+
+    pub fn reserved(&self) -> Decimal {
+        reported(self.reserved)
     }
 
     pub fn locked(&self) -> bool {
         self.locked
     }
+
+    pub(crate) fn balances(&self) -> Balances {
+        Balances {
+            available: self.available,
+            held: self.held,
+            reserved: self.reserved,
+            locked: self.locked,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::AccountId;
+
+    #[test]
+    fn deserializes_account_ids_from_numbers_and_names() {
+        let record = csv::StringRecord::from(vec!["7", "alice", "70000", "-1"]);
+        let ids = record
+            .iter()
+            .map(|field| {
+                csv::StringRecord::from(vec![field])
+                    .deserialize::<(AccountId,)>(None)
+                    .map(|(id,)| id)
+                    .map_err(|error| error.to_string())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(ids[0], Ok(AccountId::Number(7)));
+        assert_eq!(ids[1], Ok(AccountId::parse("alice", true).unwrap()));
+        assert!(ids[2].as_ref().unwrap_err().contains(
+            "invalid value: integer `70000`, expected a numeric client id or a client name"
+        ));
+        assert!(ids[3].as_ref().unwrap_err().contains(
+            "invalid value: integer `-1`, expected a numeric client id or a client name"
+        ));
+    }
 }
@@ -0,0 +1,243 @@
+use crate::account::{Account, AccountId, Balances};
+use crate::ledger::Ledger;
+use crate::store::AccountStore;
+use crate::transaction::DepositState::{ChargedBack, Deposited, Disputed, Resolved};
+use crate::transaction::TransactionType::{Deposit, Withdrawal};
+use crate::transaction::{TransactionId, TransactionType};
+use anyhow::{bail, Context, Error};
+use csv::{ReaderBuilder, StringRecord, WriterBuilder};
+use rust_decimal::Decimal;
+use std::fs;
+use std::path::Path;
+
+// A checkpoint is a headerless CSV file holding the number of input rows already processed, followed
+// by one row per account and one row per transaction stored in the ledger:
+//   rows,<count>
+//   account,<client>,<available>,<held>,<reserved>,<locked>
+//   deposit,<tx>,<client>,<amount>,<state>
+//   withdrawal,<tx>,<client>,<amount>
+// Balances are stored unrounded so a resumed run ends up in exactly the same state as an uninterrupted one
+pub(crate) fn write_checkpoint(
+    path: &Path,
+    rows: u64,
+    ledger: &Ledger,
+    accounts: &(impl AccountStore + ?Sized),
+) -> Result<(), Error> {
+    // The checkpoint is written next to its final location and then renamed, so a crash while
+    // writing never leaves behind a truncated checkpoint
+    let temporary_path = path.with_extension("tmp");
+    let mut csv = WriterBuilder::new()
+        .flexible(true)
+        .from_path(&temporary_path)?;
+
+    csv.write_record(["rows".to_string(), rows.to_string()])?;
+    for (account_id, account) in accounts.accounts() {
+        let balances = account.balances();
+        csv.write_record([
+            "account".to_string(),
+            account_id.to_string(),
+            balances.available.to_string(),
+            balances.held.to_string(),
+            balances.reserved.to_string(),
+            balances.locked.to_string(),
+        ])?;
+    }
+    for (transaction_id, account_id, transaction_type) in ledger.entries() {
+        let (kind, amount, state) = match transaction_type {
+            Deposit(amount, state) | Withdrawal(amount, state) => {
+                (transaction_type.name(), amount, state)
+            }
+            // Only deposits and withdrawals are stored in the ledger
+            _ => continue,
+        };
+        let mut record = vec![
+            kind.to_string(),
+            transaction_id.to_string(),
+            account_id.to_string(),
+            amount.to_string(),
+        ];
+        record.push(format!("{:?}", state));
+        csv.write_record(record)?;
+    }
+    csv.flush()?;
+    drop(csv);
+
+    fs::rename(&temporary_path, path)?;
+    Ok(())
+}
+
+// Restores the ledger and accounts stored in the checkpoint, returning the number of input rows
+// which were already processed when it was written
+pub(crate) fn read_checkpoint(
+    path: &Path,
+    ledger: &mut Ledger,
+    accounts: &mut (impl AccountStore + ?Sized),
+) -> Result<u64, Error> {
+    let mut csv = ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_path(path)?;
+    let mut rows = None;
+
+    for record in csv.records() {
+        let record = record?;
+        let field = |index| -> Result<&str, Error> {
+            record
+                .get(index)
+                .with_context(|| format!("Checkpoint row {:?} is missing fields", record))
+        };
+        match field(0)? {
+            "rows" => rows = Some(field(1)?.parse()?),
+            "account" => {
+                let account = Account::from_balances(Balances {
+                    available: decimal(field(2)?)?,
+                    held: decimal(field(3)?)?,
+                    reserved: decimal(field(4)?)?,
+                    locked: field(5)?.parse()?,
+                });
+                accounts.insert_account(AccountId::Number(field(1)?.parse()?), account);
+            }
+            "deposit" | "withdrawal" => {
+                ledger.restore_entry(
+                    TransactionId(field(1)?.parse()?),
+                    AccountId::Number(field(2)?.parse()?),
+                    transaction_type(&record)?,
+                );
+            }
+            unknown => bail!("Checkpoint contains an unknown {} row", unknown),
+        }
+    }
+
+    rows.with_context(|| format!("Checkpoint {} doesn't specify a row count", path.display()))
+}
+
+fn transaction_type(record: &StringRecord) -> Result<TransactionType, Error> {
+    let amount = decimal(record.get(3).unwrap_or_default())?;
+    let state = match (&record[0], record.get(4)) {
+        (_, Some("Deposited")) => Deposited,
+        (_, Some("Disputed")) => Disputed,
+        (_, Some("Resolved")) => Resolved,
+        (_, Some("ChargedBack")) => ChargedBack,
+        // Withdrawals used to be stored without a state, back when they couldn't be disputed
+        ("withdrawal", None) => Deposited,
+        _ => bail!("Checkpoint row {:?} has an invalid deposit state", record),
+    };
+    Ok(match &record[0] {
+        "withdrawal" => Withdrawal(amount, state),
+        _ => Deposit(amount, state),
+    })
+}
+
+fn decimal(value: &str) -> Result<Decimal, Error> {
+    Decimal::from_str_exact(value)
+        .with_context(|| format!("Checkpoint contains an invalid amount {}", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::checkpoint::read_checkpoint;
+    use crate::ledger::Ledger;
+    use crate::processing::tests::process_csv;
+    use crate::processing::{process_rows, Checkpointing, Processing};
+    use crate::AccountId;
+    use csv::{ReaderBuilder, Trim};
+    use std::collections::HashMap;
+
+    #[test]
+    fn resuming_from_a_checkpoint_matches_an_uninterrupted_run() {
+        let rows = [
+            "deposit,1,1,1.0001",
+            "deposit,2,2,2",
+            "withdrawal,1,3,0.5",
+            "dispute,2,2,",
+            "deposit,1,4,3",
+            "resolve,2,2,",
+            "dispute,1,4,",
+            "chargeback,1,4,",
+        ];
+        let reader = |rows: &[&str]| {
+            let csv = format!("type,client,tx,amount\n{}", rows.join("\n"));
+            ReaderBuilder::new()
+                .has_headers(true)
+                .trim(Trim::All)
+                .flexible(true)
+                .from_reader(std::io::Cursor::new(csv))
+        };
+        let path = std::env::temp_dir().join(format!("checkpoint_{}.csv", std::process::id()));
+        let checkpointing = || Checkpointing {
+            path: &path,
+            every: 2,
+        };
+
+        let (uninterrupted, _) = process_csv(reader(&rows));
+
+        // The first run is interrupted after the fifth row, so the last checkpoint covers four rows
+        let mut interrupted_ledger = Ledger::default();
+        let mut interrupted_accounts = HashMap::new();
+        process_rows(
+            &mut interrupted_ledger,
+            &mut interrupted_accounts,
+            reader(&rows[..5]).into_deserialize(),
+            &mut Processing {
+                checkpointing: Some(checkpointing()),
+                ..Default::default()
+            },
+        );
+
+        let mut ledger = Ledger::default();
+        let mut accounts = HashMap::new();
+        let processed_rows = read_checkpoint(&path, &mut ledger, &mut accounts).unwrap();
+        let errors = process_rows(
+            &mut ledger,
+            &mut accounts,
+            reader(&rows).into_deserialize(),
+            &mut Processing {
+                processed_rows,
+                checkpointing: Some(checkpointing()),
+                ..Default::default()
+            },
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(processed_rows, 4);
+        assert_eq!(errors.len(), 0);
+        assert_eq!(accounts, uninterrupted);
+        assert!(accounts.get(&AccountId::Number(1)).unwrap().locked());
+    }
+
+    #[test]
+    fn rewrites_the_whole_state_at_every_checkpoint() {
+        let path = std::env::temp_dir().join(format!("checkpoint_size_{}.csv", std::process::id()));
+        // Checkpoint rows after each stretch of deposits, every deposit opening its own account
+        let checkpoint_rows = |deposits: u32| {
+            let csv = (1..=deposits)
+                .map(|id| format!("deposit,{},{},1", id, id))
+                .collect::<Vec<_>>()
+                .join("\n");
+            let csv = format!("type,client,tx,amount\n{}", csv);
+            let reader = ReaderBuilder::new()
+                .has_headers(true)
+                .trim(Trim::All)
+                .from_reader(csv.as_bytes());
+            process_rows(
+                &mut Ledger::default(),
+                &mut HashMap::new(),
+                reader.into_deserialize(),
+                &mut Processing {
+                    checkpointing: Some(Checkpointing {
+                        path: &path,
+                        every: 10,
+                    }),
+                    ..Default::default()
+                },
+            );
+            std::fs::read_to_string(&path).unwrap().lines().count()
+        };
+
+        // Only 10 rows separate consecutive checkpoints, yet the last one holds an account and a
+        // deposit for every row processed so far, plus the row count
+        assert_eq!(checkpoint_rows(10), 1 + 2 * 10);
+        assert_eq!(checkpoint_rows(100), 1 + 2 * 100);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
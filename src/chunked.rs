@@ -0,0 +1,128 @@
+use crate::processing::LocatedRow;
+use crate::{input_reader, transactions};
+use std::io::Read;
+use std::thread;
+
+// Parses a whole CSV input on several threads, returning its rows in the same order a sequential
+// reader would yield them. The input has to be loaded into memory first, this only saves the time
+// spent parsing.
+//
+// The rows after the header are split into one byte range of roughly the same size per thread, each one extended
+// up to the next line break so no row is cut in half. Every range is then parsed on its own thread,
+// prefixed by the header so the columns are mapped identically.
+//
+// Only the parsing is parallel. Applying the transactions stays sequential because a dispute,
+// resolve or chargeback may reference a transaction found in any earlier chunk, and the duplicate
+// checks span every client. Concatenating the parsed chunks in order acts as that second pass and
+// gives exactly the same accounts and errors as the sequential reader. The positions included in
+// parsing errors are relative to the chunk the row was found in, though.
+//
+// The input format never quotes line breaks inside fields, which is what makes splitting on them
+// safe.
+pub(crate) fn parse_chunked(
+    input: &[u8],
+    threads: usize,
+    string_client_ids: bool,
+) -> Vec<LocatedRow> {
+    let header_end = next_row(input, 0);
+    let (header, body) = input.split_at(header_end);
+
+    let mut ranges = vec![];
+    let mut start = 0;
+    let chunk_size = (body.len() / threads.max(1)).max(1);
+    while start < body.len() {
+        let end = next_row(body, (start + chunk_size).min(body.len()));
+        ranges.push(&body[start..end]);
+        start = end;
+    }
+    // Rows hold no line breaks, so the rows of a chunk are its non blank lines, after the header
+    // and the lines of the chunks before it
+    let mut first_lines = vec![];
+    let mut first_line = 2;
+    for range in &ranges {
+        first_lines.push(first_line);
+        first_line += range.iter().filter(|byte| **byte == b'\n').count() as u64;
+    }
+
+    thread::scope(|scope| {
+        let parsers = ranges
+            .into_iter()
+            .zip(first_lines)
+            .map(|(range, first_line)| {
+                scope.spawn(move || {
+                    let lines = (first_line..)
+                        .zip(range.split(|byte| *byte == b'\n'))
+                        .filter(|(_, text)| !matches!(text, [] | [b'\r']))
+                        .map(|(line, _)| line);
+                    transactions(input_reader(header.chain(range)), string_client_ids)
+                        .zip(lines)
+                        .map(|(row, line)| LocatedRow { line, row })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+        parsers
+            .into_iter()
+            .flat_map(|parser| parser.join().expect("chunk parser panicked"))
+            .collect()
+    })
+}
+
+// Position right after the line break ending the row which contains the specified position
+fn next_row(input: &[u8], position: usize) -> usize {
+    input[position..]
+        .iter()
+        .position(|byte| *byte == b'\n')
+        .map_or(input.len(), |line_break| position + line_break + 1)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::chunked::parse_chunked;
+    use crate::generate::{self, GeneratorOptions};
+    use crate::ledger::Ledger;
+    use crate::processing::tests::process_csv;
+    use crate::processing::{process_rows, Processing};
+    use anyhow::Error;
+    use csv::{ReaderBuilder, Trim};
+    use std::collections::HashMap;
+
+    #[test]
+    fn chunked_reading_matches_sequential_processing() {
+        let options = GeneratorOptions {
+            rows: 3000,
+            seed: 11,
+            clients: 30,
+            dispute_ratio: 0.3,
+        };
+        let mut input = vec![];
+        generate::generate(&mut input, &options).unwrap();
+        input.extend_from_slice(b"dispute,1,999999,\nresolve,2,1,\n");
+
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(input.as_slice());
+        let (sequential_accounts, sequential_errors) = process_csv(csv);
+
+        let mut accounts = HashMap::new();
+        let errors = process_rows(
+            &mut Ledger::default(),
+            &mut accounts,
+            parse_chunked(&input, 8, false),
+            &mut Processing::default(),
+        );
+
+        assert_eq!(sequential_errors.len(), 2);
+        assert_eq!(accounts, sequential_accounts);
+        assert_eq!(
+            errors.iter().map(Error::to_string).collect::<Vec<_>>(),
+            sequential_errors
+                .iter()
+                .map(Error::to_string)
+                .collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,243 @@
+use crate::account::AccountId;
+use crate::jsonl::InvalidJsonLine;
+use crate::line_guard::LineTooLong;
+use crate::money::REPORTED_SCALE;
+use crate::transaction::{
+    FieldCountMismatch, Transaction, TransactionFailure, TransactionId, TransactionType,
+};
+use anyhow::Error;
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, Write};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+// How the processing errors are written into the errors file
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) enum ErrorsFormat {
+    // One message per line
+    #[default]
+    Text,
+    // `line,kind,client,tx,message` rows, where the client and tx are empty if the row couldn't be parsed
+    Csv,
+}
+
+// A problem found while processing the input, attributed to the line of the row which caused it.
+// Warnings flag rows which were applied but are likely not what the producer meant, while errors
+// are rows which were rejected
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Diagnostic {
+    pub line: u64,
+    pub severity: Severity,
+    pub kind: &'static str,
+    pub client: Option<AccountId>,
+    pub transaction: Option<TransactionId>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub(crate) fn warning(
+        line: u64,
+        transaction: &Transaction,
+        kind: &'static str,
+        message: String,
+    ) -> Self {
+        Diagnostic {
+            line,
+            severity: Severity::Warning,
+            kind,
+            client: Some(transaction.account_id),
+            transaction: Some(transaction.transaction_id),
+            message,
+        }
+    }
+
+    // The transaction is only known if the row could be parsed
+    pub(crate) fn error(line: u64, transaction: Option<&Transaction>, error: &Error) -> Self {
+        let kind = if let Some(failure) = error.downcast_ref::<TransactionFailure>() {
+            failure.kind()
+        } else if error.is::<LineTooLong>() {
+            "line_too_long"
+        } else if error.is::<FieldCountMismatch>() {
+            "field_count"
+        } else if error.is::<csv::Error>() || error.is::<InvalidJsonLine>() {
+            "parse"
+        } else {
+            "io"
+        };
+        Diagnostic {
+            line,
+            severity: Severity::Error,
+            kind,
+            client: transaction.map(|transaction| transaction.account_id),
+            transaction: transaction.map(|transaction| transaction.transaction_id),
+            message: error.to_string(),
+        }
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let label = match self.severity {
+            Severity::Warning => "WARN",
+            Severity::Error => "ERROR",
+        };
+        write!(f, "line {}: [{}] {}", self.line, label, self.message)
+    }
+}
+
+// Flags amounts which are more precise than what's stored
+pub(crate) fn precision_warning(line: u64, transaction: &Transaction) -> Option<Diagnostic> {
+    let amount = match transaction.transaction_type {
+        TransactionType::Deposit(amount, _)
+        | TransactionType::Withdrawal(amount, _)
+        | TransactionType::ReleaseReserve(amount) => amount,
+        TransactionType::Dispute
+        | TransactionType::Resolve
+        | TransactionType::Chargeback
+        | TransactionType::AccrueHeldInterest => return None,
+    };
+    (amount.normalize().scale() > REPORTED_SCALE).then(|| {
+        let message = format!(
+            "Transaction #{} amount {} has more than {} decimal places and will be rounded",
+            transaction.transaction_id, amount, REPORTED_SCALE
+        );
+        Diagnostic::warning(line, transaction, "excess_precision", message)
+    })
+}
+
+// Writes the errors among the diagnostics, warnings are only part of the diagnostics stream
+pub(crate) fn write_errors(
+    out: &mut impl Write,
+    diagnostics: &[Diagnostic],
+    format: ErrorsFormat,
+) -> io::Result<()> {
+    let errors = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error);
+    match format {
+        ErrorsFormat::Text => {
+            for error in errors {
+                writeln!(out, "{}", error.message)?;
+            }
+        }
+        ErrorsFormat::Csv => {
+            // Messages may contain commas, so they go through a proper CSV writer
+            let mut csv = csv::Writer::from_writer(out);
+            csv.write_record(["line", "kind", "client", "tx", "message"])?;
+            let optional = |value: Option<String>| value.unwrap_or_default();
+            for error in errors {
+                csv.write_record([
+                    error.line.to_string(),
+                    error.kind.to_string(),
+                    optional(error.client.map(|client| client.to_string())),
+                    optional(error.transaction.map(|transaction| transaction.to_string())),
+                    error.message.clone(),
+                ])?;
+            }
+            csv.flush()?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::diagnostics::{write_errors, ErrorsFormat};
+    use crate::ledger::Ledger;
+    use crate::processing::{process_rows, Processing};
+    use csv::{ReaderBuilder, Trim};
+    use std::collections::HashMap;
+
+    #[test]
+    fn diagnostics_interleave_warnings_and_errors_by_line() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,1.00005
+                        withdrawal,1,2,5
+                        deposit,1,3,2
+                        withdrawal,1,4,0.12345
+                        dispute,1,7,";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let mut diagnostics = vec![];
+        let mut processing = Processing {
+            diagnostics: Some(&mut diagnostics),
+            ..Default::default()
+        };
+        let errors = process_rows(
+            &mut Ledger::default(),
+            &mut HashMap::new(),
+            csv.into_deserialize(),
+            &mut processing,
+        );
+
+        assert_eq!(errors.len(), 2);
+        assert_eq!(
+            diagnostics.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec![
+                "line 2: [WARN] Transaction #1 amount 1.00005 has more than 4 decimal places and will be rounded",
+                "line 3: [ERROR] TransactionId(2) for AccountId(1) can't withdraw $5 due to insufficient funds",
+                "line 5: [WARN] Transaction #4 amount 0.12345 has more than 4 decimal places and will be rounded",
+                "line 6: [ERROR] TransactionId(7) not found",
+            ]
+        );
+    }
+
+    #[test]
+    fn writes_errors_as_csv_rows() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,3
+                        withdrawal,1,2,5
+                        transfer,1,3,1
+                        deposit,1,4,0.00001
+                        dispute,1,9,
+                        withdrawal,1,5,1
+                        dispute,1,5,";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let mut diagnostics = vec![];
+        let mut processing = Processing {
+            diagnostics: Some(&mut diagnostics),
+            ..Default::default()
+        };
+        let errors = process_rows(
+            &mut Ledger::default(),
+            &mut HashMap::new(),
+            csv.into_deserialize(),
+            &mut processing,
+        );
+        let mut output = vec![];
+        write_errors(&mut output, &diagnostics, ErrorsFormat::Csv).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let rows = output.lines().collect::<Vec<_>>();
+
+        assert_eq!(errors.len(), 4);
+        assert_eq!(rows.len(), 5);
+        assert_eq!(rows[0], "line,kind,client,tx,message");
+        assert_eq!(
+            rows[1],
+            "3,insufficient_funds,1,2,TransactionId(2) for AccountId(1) can't withdraw $5 due to insufficient funds"
+        );
+        assert!(rows[2].starts_with("4,parse,,,"));
+        assert_eq!(
+            rows[3],
+            "6,non_existent_transaction,1,9,TransactionId(9) not found"
+        );
+        assert_eq!(
+            rows[4],
+            "8,undisputable_withdrawal,1,5,Transaction #5 is a withdrawal and cannot be disputed"
+        );
+    }
+}
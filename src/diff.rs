@@ -0,0 +1,144 @@
+use crate::options::parse_value;
+use anyhow::{bail, Context, Error};
+use csv::{ReaderBuilder, StringRecord, Trim};
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+use std::io::Read;
+
+const COLUMNS: [&str; 4] = ["available", "held", "total", "locked"];
+
+// Settings of the `diff` subcommand, which compares two CSV reports produced by the processor
+#[derive(Debug, Default)]
+pub(crate) struct DiffOptions {
+    pub left: String,
+    pub right: String,
+    // Balances are rounded to this scale before being compared, so reports produced with different
+    // output precisions are only reported as different when their values really are
+    pub scale: Option<u32>,
+}
+
+impl DiffOptions {
+    // Parses the arguments following the `diff` subcommand
+    pub fn parse(args: &[String]) -> Result<Self, Error> {
+        let mut options = DiffOptions::default();
+        let mut paths = vec![];
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--diff-scale" => options.scale = Some(parse_value(arg, args.next())?),
+                flag if flag.starts_with("--") => bail!("Unknown diff option {}", flag),
+                path => paths.push(path.to_string()),
+            }
+        }
+
+        if paths.len() != 2 {
+            bail!("Expected 2 reports to diff, got {}", paths.len());
+        }
+        options.right = paths.remove(1);
+        options.left = paths.remove(0);
+        Ok(options)
+    }
+}
+
+// Returns a human readable line for every difference between both reports, ordered by client
+pub(crate) fn diff_reports(
+    left: impl Read,
+    right: impl Read,
+    scale: Option<u32>,
+) -> Result<Vec<String>, Error> {
+    let (left, right) = (read_report(left)?, read_report(right)?);
+    let mut differences = vec![];
+
+    for (client, left_row) in &left {
+        let right_row = match right.get(client) {
+            Some(row) => row,
+            None => {
+                differences.push(format!(
+                    "client {} is missing from the second report",
+                    client
+                ));
+                continue;
+            }
+        };
+        for (column, (left_value, right_value)) in
+            COLUMNS.iter().zip(left_row.iter().zip(right_row))
+        {
+            if !values_match(left_value, right_value, scale) {
+                differences.push(format!(
+                    "client {} {}: {} != {}",
+                    client, column, left_value, right_value
+                ));
+            }
+        }
+    }
+    for client in right.keys().filter(|client| !left.contains_key(client)) {
+        differences.push(format!(
+            "client {} is missing from the first report",
+            client
+        ));
+    }
+    Ok(differences)
+}
+
+fn values_match(left: &str, right: &str, scale: Option<u32>) -> bool {
+    match (scale, left.parse::<Decimal>(), right.parse::<Decimal>()) {
+        (Some(scale), Ok(left), Ok(right)) => left.round_dp(scale) == right.round_dp(scale),
+        _ => left == right,
+    }
+}
+
+// Reads a report into its rows keyed by client, each row holding the COLUMNS values in order
+fn read_report(report: impl Read) -> Result<BTreeMap<u16, Vec<String>>, Error> {
+    let mut csv = ReaderBuilder::new()
+        .has_headers(true)
+        .trim(Trim::All)
+        .from_reader(report);
+    let headers = csv.headers()?.clone();
+    let column = |name: &str| {
+        headers
+            .iter()
+            .position(|header| header == name)
+            .with_context(|| format!("Report is missing the {} column", name))
+    };
+    let client_column = column("client")?;
+    let value_columns = COLUMNS
+        .iter()
+        .map(|name| column(name))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut rows = BTreeMap::new();
+    for record in csv.records() {
+        let record: StringRecord = record?;
+        let client = record[client_column].parse()?;
+        let values = value_columns
+            .iter()
+            .map(|index| record[*index].to_string())
+            .collect();
+        rows.insert(client, values);
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::diff::diff_reports;
+
+    #[test]
+    fn diff_ignores_precision_differences_at_the_given_scale() {
+        let left = "client,available,held,total,locked
+                        1,1.5,0,1.5,false
+                        2,2.00004,0,2.00004,false";
+        let right = "client,available,held,total,locked
+                        2,2.0000,0.0000,2.0000,false
+                        1,1.5000,0.0000,1.6000,false";
+
+        let differences = diff_reports(left.as_bytes(), right.as_bytes(), Some(4)).unwrap();
+        assert_eq!(differences, vec!["client 1 total: 1.5 != 1.6000"]);
+
+        // Without a scale every cosmetic difference shows up
+        let differences = diff_reports(left.as_bytes(), right.as_bytes(), None).unwrap();
+        assert_eq!(differences.len(), 6);
+    }
+}
@@ -0,0 +1,196 @@
+use crate::options::parse_value;
+use anyhow::{bail, Error};
+use rust_decimal::Decimal;
+use std::io::{self, Write};
+
+// Settings of the `generate` subcommand, which outputs a random but reproducible CSV input
+#[derive(Debug)]
+pub(crate) struct GeneratorOptions {
+    pub rows: u64,
+    pub seed: u64,
+    pub clients: u16,
+    // Share of the rows which are disputes, resolves or chargebacks
+    pub dispute_ratio: f64,
+}
+
+impl Default for GeneratorOptions {
+    fn default() -> Self {
+        GeneratorOptions {
+            rows: 1000,
+            seed: 0,
+            clients: 10,
+            dispute_ratio: 0.1,
+        }
+    }
+}
+
+impl GeneratorOptions {
+    // Parses the arguments following the `generate` subcommand
+    pub fn parse(args: &[String]) -> Result<Self, Error> {
+        let mut options = GeneratorOptions::default();
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--rows" => options.rows = parse_value(arg, args.next())?,
+                "--seed" => options.seed = parse_value(arg, args.next())?,
+                "--clients" => options.clients = parse_value(arg, args.next())?,
+                "--dispute-ratio" => options.dispute_ratio = parse_value(arg, args.next())?,
+                unknown => bail!("Unknown generate option {}", unknown),
+            }
+        }
+
+        if options.clients == 0 {
+            bail!("--clients must be greater than 0");
+        }
+        if !(0.0..=1.0).contains(&options.dispute_ratio) {
+            bail!("--dispute-ratio must be between 0 and 1");
+        }
+        Ok(options)
+    }
+}
+
+// SplitMix64, a tiny PRNG which is more than good enough for fixtures and fully determined by its seed
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+
+    // Uniformly distributed value in [0, 1)
+    fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+// What the generator knows about each client, so it only emits sequences the ledger accepts
+#[derive(Default)]
+struct ClientState {
+    available: Decimal,
+    undisputed: Vec<(u32, Decimal)>,
+    disputed: Vec<(u32, Decimal)>,
+    locked: bool,
+}
+
+pub(crate) fn generate(out: &mut impl Write, options: &GeneratorOptions) -> io::Result<()> {
+    let mut random = Random(options.seed);
+    let mut clients = (0..options.clients)
+        .map(|_| ClientState::default())
+        .collect::<Vec<_>>();
+    let mut next_transaction_id = 1u32;
+
+    writeln!(out, "type,client,tx,amount")?;
+    let mut rows = 0;
+    while rows < options.rows {
+        let client_index = random.below(options.clients as u64) as usize;
+        let unlocked_clients = clients.iter().filter(|client| !client.locked).count();
+        let client = &mut clients[client_index];
+        // Locked accounts can't process anything else, so their rows are skipped and rolled again
+        if client.locked {
+            if unlocked_clients == 0 {
+                break;
+            }
+            continue;
+        }
+        let client_id = client_index + 1;
+
+        if random.unit() < options.dispute_ratio {
+            if !client.disputed.is_empty() && random.below(2) == 0 {
+                let (transaction_id, amount) = client
+                    .disputed
+                    .swap_remove(random.below(client.disputed.len() as u64) as usize);
+                // Chargebacks lock the account, which is never done to the last unlocked client
+                if random.below(5) == 0 && unlocked_clients > 1 {
+                    client.locked = true;
+                    writeln!(out, "chargeback,{},{},", client_id, transaction_id)?;
+                } else {
+                    client.available += amount;
+                    writeln!(out, "resolve,{},{},", client_id, transaction_id)?;
+                }
+                rows += 1;
+                continue;
+            }
+            if !client.undisputed.is_empty() {
+                let (transaction_id, amount) = client
+                    .undisputed
+                    .swap_remove(random.below(client.undisputed.len() as u64) as usize);
+                client.available -= amount;
+                client.disputed.push((transaction_id, amount));
+                writeln!(out, "dispute,{},{},", client_id, transaction_id)?;
+                rows += 1;
+                continue;
+            }
+        }
+
+        // Amounts go up to 100 with the four decimal places supported by the input format
+        let amount = Decimal::new(random.below(1_000_000) as i64 + 1, 4);
+        let transaction_id = next_transaction_id;
+        next_transaction_id += 1;
+        // Withdrawals stay well under the available funds so they are never rejected
+        if random.below(3) == 0 && amount * Decimal::TWO <= client.available {
+            client.available -= amount;
+            writeln!(
+                out,
+                "withdrawal,{},{},{}",
+                client_id, transaction_id, amount
+            )?;
+        } else {
+            client.available += amount;
+            client.undisputed.push((transaction_id, amount));
+            writeln!(out, "deposit,{},{},{}", client_id, transaction_id, amount)?;
+        }
+        rows += 1;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::generate::{self, GeneratorOptions};
+    use crate::processing::tests::process_csv;
+    use csv::{ReaderBuilder, Trim};
+
+    #[test]
+    fn generates_reproducible_valid_inputs() {
+        let generate = |seed| {
+            let options = GeneratorOptions {
+                rows: 2000,
+                seed,
+                clients: 20,
+                dispute_ratio: 0.3,
+            };
+            let mut output = vec![];
+            generate::generate(&mut output, &options).unwrap();
+            output
+        };
+
+        let generated = generate(7);
+        assert_eq!(generated, generate(7));
+        assert_ne!(generated, generate(8));
+
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(generated.as_slice());
+        assert_eq!(csv.into_records().count(), 2000);
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(generated.as_slice());
+        let (accounts, errors) = process_csv(csv);
+        assert!(!accounts.is_empty());
+        assert_eq!(errors.len(), 0);
+    }
+}
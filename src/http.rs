@@ -0,0 +1,60 @@
+use anyhow::{bail, Error};
+use std::io::{BufRead, BufReader};
+use std::time::Duration;
+
+// How long connecting to the server, TLS handshake included, may take
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+// How long the server may go without sending anything, while responding or streaming the body
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Requests the specified `http://` or `https://` URL, returning a reader over the response body
+// which streams it as it's received. Redirects are followed, and any final response other than
+// `200` is an error
+pub(crate) fn fetch(url: &str) -> Result<impl BufRead, Error> {
+    let agent = ureq::AgentBuilder::new()
+        .timeout_connect(CONNECT_TIMEOUT)
+        .timeout_read(READ_TIMEOUT)
+        .build();
+    let response = match agent.get(url).call() {
+        Ok(response) => response,
+        Err(ureq::Error::Status(status, response)) => {
+            bail!(
+                "{} responded with {} {}",
+                url,
+                status,
+                response.status_text()
+            )
+        }
+        Err(ureq::Error::Transport(transport)) => return Err(transport_failure(url, transport)),
+    };
+    if response.status() != 200 {
+        bail!(
+            "{} responded with {} {}",
+            url,
+            response.status(),
+            response.status_text()
+        );
+    }
+    Ok(BufReader::new(response.into_reader()))
+}
+
+// Names what went wrong before the server could respond, keeping ureq's own description as the cause
+fn transport_failure(url: &str, transport: ureq::Transport) -> Error {
+    let failure = match transport.kind() {
+        ureq::ErrorKind::Dns => format!("Cannot resolve the host of {}", url),
+        // ureq reports failed TLS handshakes, such as untrusted certificates, as failed connections
+        ureq::ErrorKind::ConnectionFailed
+            if transport
+                .message()
+                .is_some_and(|message| message.contains("tls")) =>
+        {
+            format!("Cannot establish a TLS connection to {}", url)
+        }
+        ureq::ErrorKind::ConnectionFailed => format!("Cannot connect to {}", url),
+        ureq::ErrorKind::UnknownScheme | ureq::ErrorKind::InvalidUrl => {
+            format!("{} is not a valid http:// or https:// URL", url)
+        }
+        _ => format!("Cannot fetch {}", url),
+    };
+    Error::new(transport).context(failure)
+}
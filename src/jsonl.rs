@@ -0,0 +1,266 @@
+use crate::account::AccountId;
+use crate::processing::LocatedRow;
+use crate::transaction::{
+    parse_amount, BatchId, NamedTransaction, NamedTransactionRow, Transaction, TransactionId,
+    TransactionRow,
+};
+use anyhow::Error;
+use rust_decimal::Decimal;
+use serde::de::{self, Unexpected};
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+use std::io::{self, BufRead};
+
+#[derive(Debug, thiserror::Error)]
+#[error("Line {line} is not a valid JSON transaction: {reason}")]
+pub(crate) struct InvalidJsonLine {
+    pub line: u64,
+    pub reason: String,
+}
+
+// A line of the input, whose fields are named like the columns of the CSV input
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonRow {
+    #[serde(rename = "type")]
+    transaction_type: String,
+    #[serde(deserialize_with = "client_text")]
+    client: String,
+    tx: TransactionId,
+    #[serde(default, deserialize_with = "json_amount")]
+    amount: Option<Decimal>,
+    #[serde(default)]
+    batch: Option<BatchId>,
+}
+
+// Clients can be numbers or strings, whether the text is a numeric id or a name depending on
+// --string-client-ids like it does for the CSV input
+fn client_text<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    match Value::deserialize(deserializer)? {
+        Value::String(client) => Ok(client),
+        Value::Number(client) if client.is_u64() => Ok(client.to_string()),
+        other => Err(de::Error::invalid_type(
+            unexpected(&other),
+            &"a client id or name",
+        )),
+    }
+}
+
+// Amounts can be numbers or strings. serde_json keeps the text of numbers with its
+// arbitrary_precision feature, so both are parsed like the amounts of the CSV input rather than
+// going through a float. An empty string is a missing amount, like an empty CSV field
+fn json_amount<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Decimal>, D::Error> {
+    let amount = match Option::<Value>::deserialize(deserializer)? {
+        None => return Ok(None),
+        Some(Value::String(amount)) if amount.trim().is_empty() => return Ok(None),
+        Some(Value::String(amount)) => amount,
+        Some(Value::Number(amount)) => amount.to_string(),
+        Some(other) => return Err(de::Error::invalid_type(unexpected(&other), &"an amount")),
+    };
+    parse_amount(amount.trim()).map(Some)
+}
+
+fn unexpected(value: &Value) -> Unexpected<'_> {
+    match value {
+        Value::Null => Unexpected::Unit,
+        Value::Bool(boolean) => Unexpected::Bool(*boolean),
+        Value::Number(_) => Unexpected::Other("number"),
+        Value::String(string) => Unexpected::Str(string),
+        Value::Array(_) => Unexpected::Seq,
+        Value::Object(_) => Unexpected::Map,
+    }
+}
+
+// Reads one transaction per line from flat JSON objects such as
+// `{"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}`, through the same conversion into a
+// transaction as the rows of the CSV input
+pub(crate) fn jsonl_rows<'a, R: BufRead + 'a>(
+    input: R,
+    string_client_ids: bool,
+) -> impl Iterator<Item = LocatedRow> + 'a {
+    // Blank lines are skipped, like the CSV reader does
+    (1..)
+        .zip(input.lines())
+        .filter(|(_, text)| !matches!(text, Ok(text) if text.trim().is_empty()))
+        .map(move |(line, text)| LocatedRow {
+            line,
+            row: parse_line(line, text, string_client_ids),
+        })
+}
+
+// Parses the object of a single line into a transaction
+fn parse_line(
+    line: u64,
+    text: io::Result<String>,
+    string_client_ids: bool,
+) -> Result<Transaction, Error> {
+    let invalid = |reason: String| InvalidJsonLine { line, reason };
+    let row: JsonRow = serde_json::from_str(&text?).map_err(|error| {
+        // Every object holds a single line, so only the column of the error is relevant
+        let position = format!(" at line {} column {}", error.line(), error.column());
+        let reason = error.to_string();
+        let reason = reason.strip_suffix(&position).unwrap_or(&reason);
+        invalid(format!("{} at column {}", reason, error.column()))
+    })?;
+    let transaction = if string_client_ids {
+        NamedTransaction::try_from(NamedTransactionRow {
+            transaction_type: row.transaction_type,
+            client: row.client,
+            transaction_id: row.tx,
+            amount: row.amount,
+            batch: row.batch,
+        })
+        .map(|named| named.0)
+    } else {
+        let account_id = match row.client.trim().parse() {
+            Ok(number) => AccountId::Number(number),
+            Err(_) => {
+                return Err(invalid(format!("{} is not a numeric client id", row.client)).into())
+            }
+        };
+        Transaction::try_from(TransactionRow {
+            transaction_type: row.transaction_type,
+            account_id,
+            transaction_id: row.tx,
+            amount: row.amount,
+            batch: row.batch,
+        })
+    };
+    transaction.map_err(|error| invalid(error.to_string()).into())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::jsonl::jsonl_rows;
+    use crate::ledger::Ledger;
+    use crate::processing::{process_rows, Processing};
+    use crate::transaction::DepositState::Deposited;
+    use crate::transaction::TransactionType;
+    use crate::{transactions, AccountId};
+    use csv::{ReaderBuilder, Trim};
+    use rust_decimal::Decimal;
+    use std::collections::HashMap;
+
+    #[test]
+    fn merges_csv_and_jsonl_inputs_into_the_same_ledger() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,10
+                        deposit,2,2,5";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+        let jsonl = r#"{"type": "withdrawal", "client": 1, "tx": 3, "amount": "2.5"}
+
+            {"type":"dispute","client":2,"tx":2,"amount":null}
+            {"type": "deposit", "client": 3, "tx": 4, "amount": 1.25}
+            {"type": "deposit", "client": 3, "tx": 5, "amount": 1, "note": "gift"}
+            {"type": "deposit", "client": 3, "tx": 6"#;
+
+        let mut ledger = Ledger::default();
+        let mut accounts = HashMap::new();
+        let mut processing = Processing::default();
+        let mut errors = process_rows(
+            &mut ledger,
+            &mut accounts,
+            transactions(csv, false),
+            &mut processing,
+        );
+        errors.extend(process_rows(
+            &mut ledger,
+            &mut accounts,
+            jsonl_rows(jsonl.as_bytes(), false),
+            &mut processing,
+        ));
+
+        assert_eq!(
+            accounts[&AccountId::Number(1)].available(),
+            Decimal::from_str_exact("7.5").unwrap()
+        );
+        // The JSONL dispute references a deposit of the CSV input
+        assert_eq!(accounts[&AccountId::Number(2)].held(), Decimal::from(5));
+        assert_eq!(
+            accounts[&AccountId::Number(3)].available(),
+            Decimal::from_str_exact("1.25").unwrap()
+        );
+        assert_eq!(
+            errors.iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+            [
+                "Line 5 is not a valid JSON transaction: unknown field `note`, expected one of `type`, \
+                `client`, `tx`, `amount`, `batch` at column 73",
+                "Line 6 is not a valid JSON transaction: EOF while parsing an object at column 52"
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_clients_and_amounts_of_jsonl_objects_as_numbers_or_strings() {
+        let jsonl = r#"{"type": "deposit", "client": "1", "tx": 1, "amount": " 2.50 ", "batch": null}
+            {"type": "d\u0065posit", "client": 1, "tx": 2, "amount": ""}
+            {"type": "deposit", "client": "alice", "tx": 3, "amount": 1}
+            {"type": "deposit", "client": 1, "tx": 4, "amount": true}
+            {"type": "deposit", "type": "withdrawal", "client": 1, "tx": 5, "amount": 1}"#;
+        let rows = |string_client_ids| {
+            jsonl_rows(jsonl.as_bytes(), string_client_ids)
+                .map(|row| match row.row {
+                    Ok(transaction) => Ok((transaction.account_id, transaction.transaction_type)),
+                    Err(error) => Err(error.to_string()),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let numeric = rows(false);
+        assert_eq!(
+            numeric[0],
+            Ok((
+                AccountId::Number(1),
+                TransactionType::Deposit(Decimal::new(25, 1), Deposited)
+            ))
+        );
+        // Escapes are decoded, and an empty amount is a missing one
+        assert_eq!(
+            numeric[1],
+            Err(
+                "Line 2 is not a valid JSON transaction: Transaction requires a defined amount"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            numeric[2],
+            Err(
+                "Line 3 is not a valid JSON transaction: alice is not a numeric client id"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            numeric[3],
+            Err("Line 4 is not a valid JSON transaction: invalid type: boolean `true`, expected an \
+                amount at column 69"
+                .to_string())
+        );
+        assert_eq!(
+            numeric[4],
+            Err(
+                "Line 5 is not a valid JSON transaction: duplicate field `type` at column 38"
+                    .to_string()
+            )
+        );
+        // With string client ids, numbers are names like strings are
+        let named = rows(true);
+        assert_eq!(
+            named[1].as_ref().unwrap_err(),
+            numeric[1].as_ref().unwrap_err()
+        );
+        assert_eq!(
+            named[2].as_ref().unwrap().0,
+            AccountId::parse("alice", true).unwrap()
+        );
+        assert_eq!(
+            named[0].as_ref().unwrap().0,
+            AccountId::parse("1", true).unwrap()
+        );
+        assert_ne!(named[0].as_ref().unwrap().0, AccountId::Number(1));
+    }
+}
@@ -1,88 +1,640 @@
-use crate::account::AccountId;
+use crate::account::{AccountId, BalanceOverflow};
+use crate::money::{apply_rate, is_float_artifact, stored, FLOAT_ARTIFACT_SCALE};
 use crate::transaction::DepositState::{ChargedBack, Resolved};
 use crate::transaction::TransactionFailure::{
-    InsufficientFunds, InvalidDepositTransition, InvalidTransactionReference, NonExistentAccount,
-    NonExistentTransaction,
+    BalanceOverflowed, BlockedByCooldown, ConflictingDuplicate, DepositCapExceeded,
+    DuplicateTransaction, FloatArtifact, ForeignTransactionReference, HeldCapExceeded,
+    HeldInterestNotConfigured, HeldRatioExceeded, InsufficientFunds, InvalidDepositTransition,
+    InvalidTransactionReference, LockedAccount, NonExistentAccount, NonExistentTransaction,
+    NotUnderDispute, ReserveReleaseExceeded, ReusedTransactionType, UndisputableWithdrawal,
+    WithdrawalCapExceeded,
 };
 use crate::transaction::TransactionType::{Deposit, Withdrawal};
-use crate::transaction::{DepositState, TransactionId, TransactionResult, TransactionType};
-use crate::{Account, Transaction};
-use rust_decimal::Decimal;
+use crate::transaction::{
+    DepositState, Outcome, TransactionFailure, TransactionId, TransactionResult, TransactionType,
+};
+use crate::{Account, AccountStore, Transaction};
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use DepositState::{Deposited, Disputed};
-use TransactionType::{Chargeback, Dispute, Resolve};
+use TransactionType::{AccrueHeldInterest, Chargeback, Dispute, ReleaseReserve, Resolve};
+
+// Every stored transaction remembers the account it was applied to, so per-account views of the
+// ledger (such as the list of open disputes) can be built without replaying the input
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct LedgerEntry {
+    #[serde(with = "tagged")]
+    account_id: AccountId,
+    transaction_type: TransactionType,
+}
+
+// Snapshots tag the form of every client id. Written as a plain number or string, a name such as
+// `1` would be read back as a numeric id by formats which turn map keys into strings or don't
+// tell strings and numbers apart, and JSON object keys are both
+mod tagged {
+    use crate::account::{AccountId, ClientName};
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::borrow::Cow;
+    use std::collections::HashMap;
+
+    #[derive(Serialize, Deserialize)]
+    enum TaggedAccountId<'a> {
+        Number(u16),
+        Name(Cow<'a, str>),
+    }
+
+    impl<'a> From<&'a AccountId> for TaggedAccountId<'a> {
+        fn from(account_id: &'a AccountId) -> Self {
+            match account_id {
+                AccountId::Number(number) => TaggedAccountId::Number(*number),
+                AccountId::Name(name) => TaggedAccountId::Name(Cow::Borrowed(name.as_str())),
+            }
+        }
+    }
+
+    fn untag<E: Error>(account_id: TaggedAccountId) -> Result<AccountId, E> {
+        match account_id {
+            TaggedAccountId::Number(number) => Ok(AccountId::Number(number)),
+            TaggedAccountId::Name(name) => ClientName::new(&name)
+                .map(AccountId::Name)
+                .ok_or_else(|| E::custom("client names must not be blank")),
+        }
+    }
+
+    pub fn serialize<S: Serializer>(
+        account_id: &AccountId,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        TaggedAccountId::from(account_id).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<AccountId, D::Error> {
+        untag(TaggedAccountId::deserialize(deserializer)?)
+    }
+
+    // Maps keyed by client are written as a sequence of pairs, since not every format accepts
+    // maps whose keys aren't strings
+    pub mod keys {
+        use super::*;
+
+        pub fn serialize<S: Serializer, V: Serialize>(
+            map: &HashMap<AccountId, V>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            serializer.collect_seq(
+                map.iter()
+                    .map(|(account_id, value)| (TaggedAccountId::from(account_id), value)),
+            )
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>, V: Deserialize<'de>>(
+            deserializer: D,
+        ) -> Result<HashMap<AccountId, V>, D::Error> {
+            Vec::<(TaggedAccountId, V)>::deserialize(deserializer)?
+                .into_iter()
+                .map(|(account_id, value)| Ok((untag(account_id)?, value)))
+                .collect()
+        }
+    }
+}
+
+// Behaviors of the ledger which can be toggled on top of the default processing rules
+#[derive(Copy, Clone, Debug, Default)]
+pub struct LedgerOptions {
+    // Treats a deposit redelivered with the same id, account and amount as a no-op instead of an error
+    pub merge_duplicate_deposits: bool,
+    // Maximum amount an account can hold, disputes which would go over it are rejected
+    pub max_held: Option<Decimal>,
+    // Maximum amount of a single deposit or withdrawal
+    pub max_deposit: Option<Decimal>,
+    pub max_withdrawal: Option<Decimal>,
+    // Creates a zero balance account when a withdrawal is the first transaction of a client, the
+    // withdrawal itself then fails due to insufficient funds. By default such a withdrawal is
+    // rejected because the account doesn't exist
+    pub create_on_withdrawal: bool,
+    // Percentage of every deposit which is moved into held as a rolling reserve
+    pub deposit_reserve_pct: Option<Decimal>,
+    // Multiple of the available funds an account can hold after a dispute before it's flagged or,
+    // depending on the action, rejected
+    pub max_held_ratio: Option<Decimal>,
+    pub held_ratio_action: HeldRatioAction,
+    // Percentage of the held funds credited by every accrue_held_interest row, and where it goes
+    pub held_interest_pct: Option<Decimal>,
+    pub held_interest_to: InterestDestination,
+    // Withdrawals are rejected during the specified number of transactions of the account which
+    // follow a deposit above the minimum, such as the withdrawal right after it
+    pub cooldown_min_deposit: Option<Decimal>,
+    pub cooldown_transactions: Option<usize>,
+    // Chargebacks move their funds without locking the account, for operators who only lock
+    // accounts by hand
+    pub no_lock_on_chargeback: bool,
+    // Rejects amounts more precise than a float can represent instead of rounding them, since they
+    // most likely come from a producer which serialized floats
+    pub reject_float_artifacts: bool,
+    // Lets withdrawals be disputed like deposits, holding the withdrawn funds until the dispute is
+    // settled. By default a dispute referencing a withdrawal is rejected
+    pub dispute_withdrawals: bool,
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum HeldRatioAction {
+    // The dispute is applied and reported as a warning
+    #[default]
+    Warn,
+    Reject,
+}
+
+// Balance credited with the interest earned by the held funds
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum InterestDestination {
+    #[default]
+    Available,
+    // Stays held as part of the reserve, so it can be released like the rest of it
+    Held,
+}
+
+// Callback notified with the transaction and the resulting account snapshot whenever a transaction
+// is applied to the account it was subscribed to
+pub type AccountListener = Box<dyn FnMut(&Transaction, &Account)>;
+
+// State of the accounts and stored transactions before the open batch touched them, so the
+// batch can be reverted as a whole
+#[derive(Default)]
+struct BatchUndo {
+    accounts: HashMap<AccountId, Option<Account>>,
+    transactions: HashMap<TransactionId, Option<LedgerEntry>>,
+    since_large_deposit: HashMap<AccountId, Option<usize>>,
+    applied: usize,
+    // Listeners are only notified once the batch commits, with the account as each row left it
+    notifications: Vec<(Transaction, Account)>,
+}
+
+// Everything the ledger remembers from the transactions it applied, so processing can carry on in
+// a later run: disputes can then reference the transactions of the earlier inputs and cooldowns
+// keep counting. Numeric and named client ids are both restored as they were
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct LedgerState {
+    transactions: HashMap<TransactionId, LedgerEntry>,
+    #[serde(with = "tagged::keys")]
+    since_large_deposit: HashMap<AccountId, usize>,
+}
 
 #[derive(Default)]
-pub(crate) struct Ledger {
-    transactions: HashMap<TransactionId, TransactionType>,
+pub struct Ledger {
+    transactions: HashMap<TransactionId, LedgerEntry>,
+    options: LedgerOptions,
+    listeners: HashMap<AccountId, Vec<AccountListener>>,
+    batch: Option<BatchUndo>,
+    // Transactions of every account processed since its last deposit above the cooldown minimum
+    since_large_deposit: HashMap<AccountId, usize>,
 }
 
 impl Ledger {
-    pub fn new() -> Self {
+    pub fn with_options(options: LedgerOptions) -> Self {
         Ledger {
+            options,
             ..Default::default()
         }
     }
 
+    // Restores the state snapshotted by an earlier ledger, applying the specified rules from now on
+    pub fn from_snapshot(state: LedgerState, options: LedgerOptions) -> Self {
+        let LedgerState {
+            transactions,
+            since_large_deposit,
+        } = state;
+        Ledger {
+            transactions,
+            since_large_deposit,
+            options,
+            ..Default::default()
+        }
+    }
+
+    // Meant to be taken between inputs, processing an input always commits its last batch
+    pub fn snapshot(&self) -> LedgerState {
+        LedgerState {
+            transactions: self.transactions.clone(),
+            since_large_deposit: self.since_large_deposit.clone(),
+        }
+    }
+
+    // Registers a callback fired after every successfully applied transaction touching the account.
+    // The transactions of a batch are only notified once it commits, and never if it's rolled back
+    pub fn on_account_change(
+        &mut self,
+        account_id: AccountId,
+        listener: impl FnMut(&Transaction, &Account) + 'static,
+    ) {
+        self.listeners
+            .entry(account_id)
+            .or_default()
+            .push(Box::new(listener));
+    }
+
+    // Records every change made from now on until the batch is committed or rolled back
+    pub fn begin_batch(&mut self) {
+        self.batch = Some(BatchUndo::default());
+    }
+
+    pub fn commit_batch(&mut self) {
+        if let Some(undo) = self.batch.take() {
+            for (transaction, account) in undo.notifications {
+                self.notify(&transaction, &account);
+            }
+        }
+    }
+
+    // Reverts every transaction applied since the batch began, returning how many there were
+    pub fn rollback_batch(&mut self, accounts: &mut (impl AccountStore + ?Sized)) -> usize {
+        let undo = match self.batch.take() {
+            Some(undo) => undo,
+            None => return 0,
+        };
+        for (account_id, account) in undo.accounts {
+            match account {
+                Some(account) => accounts.insert_account(account_id, account),
+                None => accounts.remove_account(&account_id),
+            };
+        }
+        for (transaction_id, entry) in undo.transactions {
+            match entry {
+                Some(entry) => self.transactions.insert(transaction_id, entry),
+                None => self.transactions.remove(&transaction_id),
+            };
+        }
+        for (account_id, since_deposit) in undo.since_large_deposit {
+            match since_deposit {
+                Some(since_deposit) => self.since_large_deposit.insert(account_id, since_deposit),
+                None => self.since_large_deposit.remove(&account_id),
+            };
+        }
+        undo.applied
+    }
+
     pub fn process_transaction(
         &mut self,
-        accounts: &mut HashMap<AccountId, Account>,
+        accounts: &mut (impl AccountStore + ?Sized),
         transaction: Transaction,
-    ) -> TransactionResult {
+    ) -> Result<Outcome, TransactionFailure> {
+        if let Deposit(amount, _) | Withdrawal(amount, _) | ReleaseReserve(amount) =
+            transaction.transaction_type
+        {
+            if self.options.reject_float_artifacts && is_float_artifact(amount) {
+                return Err(FloatArtifact(
+                    transaction.transaction_id,
+                    amount,
+                    FLOAT_ARTIFACT_SCALE,
+                ));
+            }
+        }
+        // Amounts are rounded as they enter the ledger, so redelivered deposits are compared and
+        // listeners are notified with the amounts which were actually applied
+        let transaction = Transaction {
+            transaction_type: match transaction.transaction_type {
+                Deposit(amount, state) => Deposit(stored(amount), state),
+                Withdrawal(amount, state) => Withdrawal(stored(amount), state),
+                ReleaseReserve(amount) => ReleaseReserve(stored(amount)),
+                other => other,
+            },
+            ..transaction
+        };
         let Transaction {
             transaction_type,
             account_id,
             transaction_id,
+            ..
         } = &transaction;
 
+        // Deposits and withdrawals introduce new transaction ids, reusing one would overwrite the original entry
+        if let Deposit(..) | Withdrawal(..) = transaction_type {
+            if let Some(original) = self.transactions.get(transaction_id) {
+                return self
+                    .handle_duplicate(*original, &transaction)
+                    .map(|()| Outcome::Ignored);
+            }
+        }
+
+        // A transaction only ever changes its own account and ledger entry, so those are the only
+        // states a batch needs to restore
+        if let Some(undo) = &mut self.batch {
+            undo.accounts
+                .entry(*account_id)
+                .or_insert_with(|| accounts.account(account_id).cloned());
+            undo.transactions
+                .entry(*transaction_id)
+                .or_insert_with(|| self.transactions.get(transaction_id).copied());
+            undo.since_large_deposit
+                .entry(*account_id)
+                .or_insert_with(|| self.since_large_deposit.get(account_id).copied());
+        }
+        // Every transaction following a large deposit counts towards its cooldown, including the
+        // rejected ones, so retrying a blocked withdrawal eventually goes through
+        if let Some(since_deposit) = self.since_large_deposit.get_mut(account_id) {
+            *since_deposit = since_deposit.saturating_add(1);
+        }
+        self.apply_transaction(accounts, &transaction)?;
+        if let Some(account) = accounts.account(account_id) {
+            match &mut self.batch {
+                Some(undo) if self.listeners.contains_key(account_id) => {
+                    undo.notifications.push((transaction, account.clone()))
+                }
+                Some(_) => {}
+                None => self.notify(&transaction, account),
+            }
+        }
+        if let Some(undo) = &mut self.batch {
+            undo.applied += 1;
+        }
+        Ok(Outcome::Applied)
+    }
+
+    fn notify(&mut self, transaction: &Transaction, account: &Account) {
+        if let Some(listeners) = self.listeners.get_mut(&transaction.account_id) {
+            for listener in listeners {
+                listener(transaction, account);
+            }
+        }
+    }
+
+    fn apply_transaction(
+        &mut self,
+        accounts: &mut (impl AccountStore + ?Sized),
+        transaction: &Transaction,
+    ) -> TransactionResult {
+        let Transaction {
+            transaction_type,
+            account_id,
+            transaction_id,
+            ..
+        } = transaction;
+
+        // Single transaction caps are checked first, so an oversized deposit can't create an account
+        match (
+            transaction_type,
+            self.options.max_deposit,
+            self.options.max_withdrawal,
+        ) {
+            (Deposit(amount, _), Some(max_deposit), _) if *amount > max_deposit => {
+                return Err(DepositCapExceeded(*transaction_id, *amount, max_deposit));
+            }
+            (Withdrawal(amount, _), _, Some(max_withdrawal)) if *amount > max_withdrawal => {
+                return Err(WithdrawalCapExceeded(
+                    *transaction_id,
+                    *amount,
+                    max_withdrawal,
+                ));
+            }
+            _ => {}
+        }
+
         // An account is "created" when a deposit is made into a new AccountId
         // Any other transaction appearing before the account creation should be considered invalid
         let account = match transaction_type {
             // Get the existing account or create a new one
-            Deposit(..) => accounts.entry(*account_id).or_insert_with(Account::new),
+            Deposit(..) => accounts.open_account(*account_id),
+            Withdrawal(..) if self.options.create_on_withdrawal => {
+                accounts.open_account(*account_id)
+            }
             // Get the existing account or fail immediately
-            Withdrawal(_) | Dispute | Resolve | Chargeback => accounts
-                .get_mut(account_id)
+            Withdrawal(..) | Dispute | Resolve | Chargeback | ReleaseReserve(_)
+            | AccrueHeldInterest => accounts
+                .account_mut(account_id)
                 .ok_or(NonExistentAccount(*account_id))?,
         };
+        // Charged back accounts are frozen, except for resolving the disputes still open on them.
+        // A resolve only moves held funds back into available, and those can't be withdrawn while
+        // the account is locked
+        if account.locked() && !matches!(transaction_type, Resolve) {
+            return Err(LockedAccount(*account_id, *transaction_id));
+        }
 
-        let mut handle_dispute = |expected_state, new_state, operation| {
+        let overflowed = |_: BalanceOverflow| BalanceOverflowed(*transaction_id, *account_id);
+        let mut handle_dispute = |expected_state, new_state| {
+            // The referenced transaction must belong to the same client, which with
+            // --string-client-ids means the exact same text
+            match self.transactions.get(transaction_id) {
+                Some(reference) if reference.account_id != *account_id => {
+                    return Err(ForeignTransactionReference(*transaction_id, *account_id))
+                }
+                _ => {}
+            }
             self.handle_referential_transaction(
                 account,
                 *transaction_id,
                 *transaction_type,
                 expected_state,
                 new_state,
-                operation,
             )
         };
 
         match transaction_type {
             // No need to check the state of the deposit since it comes from the CSV
+            // The reserve is taken from a copy, so the deposit isn't left half applied if it overflows
             Deposit(deposit, _) => {
-                account.deposit(*deposit);
-                self.transactions.insert(*transaction_id, *transaction_type);
+                let mut deposited = account.clone();
+                deposited.deposit(*deposit).map_err(overflowed)?;
+                if let Some(reserve_pct) = self.options.deposit_reserve_pct {
+                    apply_rate(
+                        *deposit,
+                        reserve_pct / Decimal::ONE_HUNDRED,
+                        4,
+                        RoundingStrategy::MidpointNearestEven,
+                    )
+                    .ok_or(BalanceOverflow)
+                    .and_then(|reserve| deposited.reserve(reserve))
+                    .map_err(overflowed)?;
+                }
+                *account = deposited;
+                if matches!(self.options.cooldown_min_deposit, Some(min_deposit) if *deposit > min_deposit)
+                {
+                    self.since_large_deposit.insert(*account_id, 0);
+                }
+                self.record(*account_id, *transaction_id, *transaction_type);
                 Ok(())
             }
-            // A withdrawal can fail if the user tries to withdraw more funds than they have available
-            Withdrawal(withdrawal) => {
+            // A withdrawal can fail if the user tries to withdraw more funds than they have available,
+            // which never include the funds held by the disputes applied before it
+            Withdrawal(withdrawal, _) => {
+                if let (Some(cooldown), Some(since_deposit)) = (
+                    self.options.cooldown_transactions,
+                    self.since_large_deposit.get(account_id),
+                ) {
+                    if *since_deposit <= cooldown {
+                        return Err(BlockedByCooldown(*transaction_id));
+                    }
+                }
                 if account.available() < *withdrawal {
                     return Err(InsufficientFunds(*account_id, *transaction_id, *withdrawal));
                 }
-                account.withdraw(*withdrawal);
-                self.transactions.insert(*transaction_id, *transaction_type);
+                account.withdraw(*withdrawal).map_err(overflowed)?;
+                self.record(*account_id, *transaction_id, *transaction_type);
                 Ok(())
             }
             // Disputes can only be triggered once
-            Dispute => handle_dispute(Deposited, Disputed, Account::dispute),
+            Dispute => handle_dispute(Deposited, Disputed),
             // Resolves/Chargebacks can only be triggered on non-finalized transactions, and require a previous dispute to exist
-            Resolve => handle_dispute(Disputed, Resolved, Account::resolve),
-            Chargeback => handle_dispute(Disputed, ChargedBack, Account::chargeback),
+            Resolve => handle_dispute(Disputed, Resolved),
+            Chargeback => handle_dispute(Disputed, ChargedBack),
+            // Only the reserved portion of the held funds can be released, funds held by disputes
+            // stay held until the dispute is resolved or charged back
+            ReleaseReserve(released) => {
+                if account.reserved() < *released {
+                    return Err(ReserveReleaseExceeded(
+                        *transaction_id,
+                        *released,
+                        *account_id,
+                        account.reserved(),
+                    ));
+                }
+                account.release_reserve(*released).map_err(overflowed)
+            }
+            // Interest is earned on every held fund, be it held by disputes or by the reserve
+            AccrueHeldInterest => {
+                let interest_pct = self
+                    .options
+                    .held_interest_pct
+                    .ok_or(HeldInterestNotConfigured(*transaction_id))?;
+                let interest = apply_rate(
+                    account.balances().held,
+                    interest_pct / Decimal::ONE_HUNDRED,
+                    4,
+                    RoundingStrategy::MidpointNearestEven,
+                )
+                .ok_or(BalanceOverflow)
+                .map_err(overflowed)?;
+                match self.options.held_interest_to {
+                    InterestDestination::Available => account.deposit(interest),
+                    InterestDestination::Held => account.credit_reserve(interest),
+                }
+                .map_err(overflowed)
+            }
         }
     }
 
+    // Flags an applied transaction which is accepted by the ledger but worth a review, given the
+    // resulting state of its account
+    pub fn warning(&self, transaction: &Transaction, account: &Account) -> Option<String> {
+        let max_held_ratio = self.options.max_held_ratio?;
+        let over_ratio = match max_held_ratio.checked_mul(account.available()) {
+            Some(limit) => account.held() > limit,
+            // A limit overflowing upwards is beyond any held amount, one overflowing downwards below
+            None => max_held_ratio.is_sign_negative() != account.available().is_sign_negative(),
+        };
+        let breached = transaction.transaction_type == Dispute
+            && self.options.held_ratio_action == HeldRatioAction::Warn
+            && over_ratio;
+        breached.then(|| {
+            format!(
+                "Transaction #{} pushed the held funds of account #{} over {} times its available funds",
+                transaction.transaction_id, transaction.account_id, max_held_ratio
+            )
+        })
+    }
+
+    // Lists the deposits and withdrawals currently under dispute for the specified account along with the amount
+    // each of them is holding, ordered by transaction id
+    pub fn open_disputes(&self, account_id: AccountId) -> Vec<(TransactionId, Decimal)> {
+        self.all_open_disputes()
+            .into_iter()
+            .filter(|(_, disputed_account_id, _)| *disputed_account_id == account_id)
+            .map(|(transaction_id, _, amount)| (transaction_id, amount))
+            .collect()
+    }
+
+    // Every deposit and withdrawal currently under dispute along with its account and held amount, ordered by transaction id
+    pub fn all_open_disputes(&self) -> Vec<(TransactionId, AccountId, Decimal)> {
+        let mut disputes = self
+            .transactions
+            .iter()
+            .filter_map(|(transaction_id, entry)| match entry.transaction_type {
+                Deposit(amount, Disputed) | Withdrawal(amount, Disputed) => {
+                    Some((*transaction_id, entry.account_id, amount))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        disputes.sort_by_key(|(transaction_id, ..)| *transaction_id);
+        disputes
+    }
+
+    // A duplicate id is only accepted when merging is enabled and the transaction is an exact redelivery
+    // of the original deposit, in which case it has already been applied and nothing else needs to happen
+    fn handle_duplicate(
+        &self,
+        original: LedgerEntry,
+        duplicate: &Transaction,
+    ) -> TransactionResult {
+        match (original.transaction_type, duplicate.transaction_type) {
+            (Deposit(original_amount, _), Deposit(amount, _))
+                if self.options.merge_duplicate_deposits
+                    && original.account_id == duplicate.account_id =>
+            {
+                if original_amount == amount {
+                    Ok(())
+                } else {
+                    Err(ConflictingDuplicate(
+                        duplicate.transaction_id,
+                        original_amount,
+                        amount,
+                    ))
+                }
+            }
+            // Reusing an id for another type is most likely a different transaction altogether
+            // rather than a redelivery, so it gets its own error
+            (original_type, duplicate_type) if original_type.name() != duplicate_type.name() => {
+                Err(ReusedTransactionType(duplicate.transaction_id))
+            }
+            _ => Err(DuplicateTransaction(duplicate.transaction_id)),
+        }
+    }
+
+    // Amount of the stored deposit or withdrawal with the specified id
+    pub fn amount(&self, transaction_id: TransactionId) -> Option<Decimal> {
+        match self.transactions.get(&transaction_id)?.transaction_type {
+            Deposit(amount, _) | Withdrawal(amount, _) => Some(amount),
+            _ => None,
+        }
+    }
+
+    // Every deposit and withdrawal stored in the ledger along with the account it belongs to
+    pub fn entries(
+        &self,
+    ) -> impl Iterator<Item = (TransactionId, AccountId, TransactionType)> + '_ {
+        self.transactions.iter().map(|(transaction_id, entry)| {
+            (*transaction_id, entry.account_id, entry.transaction_type)
+        })
+    }
+
+    // Stores a previously persisted transaction without applying it to any account
+    pub fn restore_entry(
+        &mut self,
+        transaction_id: TransactionId,
+        account_id: AccountId,
+        transaction_type: TransactionType,
+    ) {
+        self.record(account_id, transaction_id, transaction_type);
+    }
+
+    fn record(
+        &mut self,
+        account_id: AccountId,
+        transaction_id: TransactionId,
+        transaction_type: TransactionType,
+    ) {
+        self.transactions.insert(
+            transaction_id,
+            LedgerEntry {
+                account_id,
+                transaction_type,
+            },
+        );
+    }
+
     // When dealing with disputes, resolves and chargebacks verify the referenced transaction is
-    // a deposit and in a valid state before adding it to the ledger
+    // a deposit or a withdrawal in a valid state before adding it to the ledger
     fn handle_referential_transaction(
         &mut self,
         account: &mut Account,
@@ -90,27 +642,1212 @@ impl Ledger {
         transaction_type: TransactionType,
         expected_state: DepositState,
         new_state: DepositState,
-        operation: fn(&mut Account, Decimal),
     ) -> TransactionResult {
-        let reference = self.transactions.get(&transaction_id);
+        let reference = self.transactions.get(&transaction_id).copied();
         match reference {
-            Some(Deposit(amount, state)) if *state == expected_state => {
-                operation(account, *amount);
-                self.transactions
-                    .insert(transaction_id, Deposit(*amount, new_state));
+            // Unless enabled, only deposits can be disputed, so a withdrawal gets a dedicated error
+            // instead of going through the dispute state machine
+            Some(LedgerEntry {
+                transaction_type: Withdrawal(..),
+                ..
+            }) if transaction_type == Dispute && !self.options.dispute_withdrawals => {
+                Err(UndisputableWithdrawal(transaction_id))
+            }
+            Some(LedgerEntry {
+                account_id,
+                transaction_type: reference @ (Deposit(amount, state) | Withdrawal(amount, state)),
+            }) if state == expected_state => {
+                // A disputed deposit moves its funds from available to held, while the funds of a
+                // disputed withdrawal already left the account and are held on top of the rest
+                type Operation = fn(&mut Account, Decimal) -> Result<(), BalanceOverflow>;
+                let (operation, available_after_dispute): (Operation, _) =
+                    match (reference, new_state) {
+                        (Deposit(..), Disputed) => {
+                            (Account::dispute, account.available().saturating_sub(amount))
+                        }
+                        (Deposit(..), Resolved) => (Account::resolve, account.available()),
+                        (Deposit(..), _) => (Account::chargeback, account.available()),
+                        (_, Disputed) => (Account::dispute_withdrawal, account.available()),
+                        (_, Resolved) => (Account::resolve_withdrawal, account.available()),
+                        (_, _) => (Account::chargeback_withdrawal, account.available()),
+                    };
+                // Only disputes increase the held funds, so resolves and chargebacks are never capped
+                if let Some(max_held) = self.options.max_held {
+                    if new_state == Disputed && account.held().saturating_add(amount) > max_held {
+                        return Err(HeldCapExceeded(transaction_id, account_id));
+                    }
+                }
+                if let (Some(max_held_ratio), HeldRatioAction::Reject) =
+                    (self.options.max_held_ratio, self.options.held_ratio_action)
+                {
+                    let held = account.held().saturating_add(amount);
+                    if new_state == Disputed
+                        && held > max_held_ratio.saturating_mul(available_after_dispute)
+                    {
+                        return Err(HeldRatioExceeded(
+                            transaction_id,
+                            account_id,
+                            max_held_ratio,
+                        ));
+                    }
+                }
+                operation(account, amount)
+                    .map_err(|_| BalanceOverflowed(transaction_id, account_id))?;
+                if new_state == ChargedBack && !self.options.no_lock_on_chargeback {
+                    account.lock();
+                }
+                let settled = match reference {
+                    Deposit(..) => Deposit(amount, new_state),
+                    _ => Withdrawal(amount, new_state),
+                };
+                self.record(account_id, transaction_id, settled);
                 Ok(())
             }
-            Some(Deposit(_, invalid_previous_state)) => Err(InvalidDepositTransition(
+            // Settling a transaction requires an open dispute, so a resolve or chargeback of one
+            // which was never disputed or was already settled can't apply it a second time
+            Some(LedgerEntry {
+                transaction_type: Deposit(..) | Withdrawal(..),
+                ..
+            }) if expected_state == Disputed => Err(NotUnderDispute(transaction_id)),
+            Some(LedgerEntry {
+                transaction_type:
+                    Deposit(_, invalid_previous_state) | Withdrawal(_, invalid_previous_state),
+                ..
+            }) => Err(InvalidDepositTransition(
                 transaction_id,
-                *invalid_previous_state,
+                invalid_previous_state,
                 new_state,
             )),
-            Some(invalid_reference) => Err(InvalidTransactionReference(
+            Some(LedgerEntry {
+                transaction_type: invalid_reference,
+                ..
+            }) => Err(InvalidTransactionReference(
                 transaction_id,
                 transaction_type,
-                *invalid_reference,
+                invalid_reference,
             )),
             None => Err(NonExistentTransaction(transaction_id)),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+
+    use crate::ledger::{HeldRatioAction, InterestDestination, Ledger, LedgerOptions, LedgerState};
+    use crate::options::Options;
+    use crate::processing::tests::{process_csv, process_csv_with_ledger};
+    use crate::processing::{process_rows, Processing};
+    use crate::report::{write_report, OutputFormat, Report};
+    use crate::transaction::{DepositState, TransactionId, TransactionType};
+    use crate::{transactions, AccountId, Transaction};
+    use anyhow::Error;
+    use csv::{ReaderBuilder, Trim};
+    use rust_decimal::Decimal;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    #[test]
+    fn rejects_duplicate_transaction_ids() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,1
+                        deposit,1,1,1
+                        withdrawal,1,1,0.5";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let (accounts, errors) = process_csv(csv);
+        let first_account = accounts.get(&AccountId::Number(1)).unwrap();
+        assert_eq!(first_account.total(), Decimal::from_str_exact("1").unwrap());
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].to_string(), "TransactionId(1) already exists");
+        assert_eq!(
+            errors[1].to_string(),
+            "Transaction #1 id reused with different type"
+        );
+    }
+
+    #[test]
+    fn rejects_transaction_ids_reused_with_another_type() {
+        let csv = "type,client,tx,amount
+                        deposit,1,5,10
+                        withdrawal,1,6,2
+                        withdrawal,1,5,3
+                        deposit,2,6,4
+                        withdrawal,2,6,4";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let (accounts, errors) = process_csv(csv);
+        assert_eq!(
+            accounts.get(&AccountId::Number(1)).unwrap().total(),
+            Decimal::from(8)
+        );
+        assert!(!accounts.contains_key(&AccountId::Number(2)));
+        let errors = errors
+            .iter()
+            .map(|error| error.to_string())
+            .collect::<Vec<_>>();
+        // Reusing an id with the same type is still reported as a plain duplicate
+        assert_eq!(
+            errors,
+            [
+                "Transaction #5 id reused with different type",
+                "Transaction #6 id reused with different type",
+                "TransactionId(6) already exists",
+            ]
+        );
+    }
+
+    #[test]
+    fn merges_identical_duplicate_deposits() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,1.5
+                        deposit,1,1,1.5
+                        dispute,1,1,
+                        deposit,1,1,1.5";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let mut ledger = Ledger::with_options(LedgerOptions {
+            merge_duplicate_deposits: true,
+            ..Default::default()
+        });
+        let (accounts, errors) = process_csv_with_ledger(&mut ledger, csv);
+        let first_account = accounts.get(&AccountId::Number(1)).unwrap();
+        assert_eq!(
+            first_account.available(),
+            Decimal::from_str_exact("0").unwrap()
+        );
+        assert_eq!(
+            first_account.held(),
+            Decimal::from_str_exact("1.5").unwrap()
+        );
+        assert_eq!(errors.len(), 0);
+    }
+
+    #[test]
+    fn rejects_merged_duplicate_deposits_with_conflicting_amounts() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,1.5
+                        deposit,1,1,2";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let mut ledger = Ledger::with_options(LedgerOptions {
+            merge_duplicate_deposits: true,
+            ..Default::default()
+        });
+        let (accounts, errors) = process_csv_with_ledger(&mut ledger, csv);
+        let first_account = accounts.get(&AccountId::Number(1)).unwrap();
+        assert_eq!(
+            first_account.total(),
+            Decimal::from_str_exact("1.5").unwrap()
+        );
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "TransactionId(1) was already deposited with amount 1.5 and cannot be redelivered with amount 2"
+        );
+    }
+
+    #[test]
+    fn rejects_disputes_referencing_withdrawals() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,2
+                        withdrawal,1,2,0.5
+                        dispute,1,2,";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let (accounts, errors) = process_csv(csv);
+        let first_account = accounts.get(&AccountId::Number(1)).unwrap();
+        assert_eq!(
+            first_account.available(),
+            Decimal::from_str_exact("1.5").unwrap()
+        );
+        assert_eq!(first_account.held(), Decimal::from_str_exact("0").unwrap());
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "Transaction #2 is a withdrawal and cannot be disputed"
+        );
+    }
+
+    // Applies the rows one by one with withdrawal disputes enabled, returning the balances of the
+    // first client after each of them
+    fn balances_after_each_row(rows: &[&str]) -> Vec<(Decimal, Decimal, Decimal)> {
+        let mut ledger = Ledger::with_options(LedgerOptions {
+            dispute_withdrawals: true,
+            ..Default::default()
+        });
+        let mut accounts = HashMap::new();
+        rows.iter()
+            .map(|row| {
+                let csv = format!("type,client,tx,amount\n{}", row);
+                let csv = ReaderBuilder::new()
+                    .has_headers(true)
+                    .trim(Trim::All)
+                    .flexible(true)
+                    .from_reader(csv.as_bytes());
+                let errors = process_rows(
+                    &mut ledger,
+                    &mut accounts,
+                    transactions(csv, false),
+                    &mut Processing::default(),
+                );
+                assert!(errors.is_empty(), "{} failed: {:?}", row, errors);
+                let account = &accounts[&AccountId::Number(1)];
+                (account.available(), account.held(), account.total())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn holds_disputed_withdrawals_until_resolved() {
+        let balances = balances_after_each_row(&[
+            "deposit,1,1,2",
+            "withdrawal,1,2,0.5",
+            "dispute,1,2,",
+            "resolve,1,2,",
+        ]);
+
+        let (two, half) = (Decimal::TWO, Decimal::from_str_exact("0.5").unwrap());
+        let one_and_half = Decimal::from_str_exact("1.5").unwrap();
+        // The withdrawn funds are held on top of the available ones, and the resolved withdrawal
+        // stands
+        assert_eq!(
+            balances,
+            [
+                (two, Decimal::ZERO, two),
+                (one_and_half, Decimal::ZERO, one_and_half),
+                (one_and_half, half, two),
+                (one_and_half, Decimal::ZERO, one_and_half),
+            ]
+        );
+    }
+
+    #[test]
+    fn credits_charged_back_withdrawals_back() {
+        let balances = balances_after_each_row(&[
+            "deposit,1,1,2",
+            "withdrawal,1,2,0.5",
+            "dispute,1,2,",
+            "chargeback,1,2,",
+        ]);
+
+        let (two, half) = (Decimal::TWO, Decimal::from_str_exact("0.5").unwrap());
+        let one_and_half = Decimal::from_str_exact("1.5").unwrap();
+        assert_eq!(
+            balances,
+            [
+                (two, Decimal::ZERO, two),
+                (one_and_half, Decimal::ZERO, one_and_half),
+                (one_and_half, half, two),
+                (two, Decimal::ZERO, two),
+            ]
+        );
+    }
+
+    #[test]
+    fn notifies_account_listeners_after_each_applied_transaction() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,2
+                        deposit,2,2,5
+                        withdrawal,1,3,0.5
+                        withdrawal,1,4,10
+                        dispute,1,1,
+                        dispute,2,2,
+                        resolve,1,1";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let snapshots = Rc::new(RefCell::new(vec![]));
+        let mut ledger = Ledger::default();
+        let recorded = snapshots.clone();
+        ledger.on_account_change(AccountId::Number(1), move |transaction, account| {
+            recorded.borrow_mut().push((
+                transaction.transaction_id.0,
+                account.available(),
+                account.held(),
+            ))
+        });
+        let (_, errors) = process_csv_with_ledger(&mut ledger, csv);
+
+        let decimal = |value| Decimal::from_str_exact(value).unwrap();
+        assert_eq!(errors.len(), 1);
+        // The rejected withdrawal and every transaction of the second client are not notified
+        assert_eq!(
+            *snapshots.borrow(),
+            vec![
+                (1, decimal("2"), decimal("0")),
+                (3, decimal("1.5"), decimal("0")),
+                (1, decimal("-0.5"), decimal("2")),
+                (1, decimal("1.5"), decimal("0")),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_disputes_exceeding_the_held_cap() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,3
+                        deposit,1,2,1
+                        deposit,1,3,1.5
+                        dispute,1,1,
+                        dispute,1,2,
+                        dispute,1,3,
+                        resolve,1,1,
+                        dispute,1,3";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let mut ledger = Ledger::with_options(LedgerOptions {
+            max_held: Some(Decimal::from_str_exact("4.5").unwrap()),
+            ..Default::default()
+        });
+        let (accounts, errors) = process_csv_with_ledger(&mut ledger, csv);
+        let first_account = accounts.get(&AccountId::Number(1)).unwrap();
+        assert_eq!(
+            first_account.held(),
+            Decimal::from_str_exact("2.5").unwrap()
+        );
+        assert_eq!(
+            first_account.total(),
+            Decimal::from_str_exact("5.5").unwrap()
+        );
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "Transaction #3 would exceed held cap for account #1"
+        );
+    }
+
+    #[test]
+    fn enforces_per_type_amount_caps() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,50
+                        deposit,1,2,90
+                        deposit,2,3,90
+                        deposit,1,4,70
+                        withdrawal,1,5,90
+                        withdrawal,1,6,110";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let mut ledger = Ledger::with_options(LedgerOptions {
+            max_deposit: Some(Decimal::from(80)),
+            max_withdrawal: Some(Decimal::from(100)),
+            ..Default::default()
+        });
+        let (accounts, errors) = process_csv_with_ledger(&mut ledger, csv);
+
+        // A deposit of 90 is over its cap while a withdrawal of 90 is not
+        let first_account = accounts.get(&AccountId::Number(1)).unwrap();
+        assert_eq!(first_account.available(), Decimal::from(30));
+        assert!(!accounts.contains_key(&AccountId::Number(2)));
+        assert_eq!(errors.len(), 3);
+        assert_eq!(
+            errors[0].to_string(),
+            "Transaction #2 deposits $90 which exceeds the maximum deposit of $80"
+        );
+        assert_eq!(
+            errors[1].to_string(),
+            "Transaction #3 deposits $90 which exceeds the maximum deposit of $80"
+        );
+        assert_eq!(
+            errors[2].to_string(),
+            "Transaction #6 withdraws $110 which exceeds the maximum withdrawal of $100"
+        );
+    }
+
+    #[test]
+    fn rejects_leading_withdrawals_by_default() {
+        let csv = "type,client,tx,amount
+                        withdrawal,1,1,1
+                        deposit,1,2,2";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let (accounts, errors) = process_csv(csv);
+        let first_account = accounts.get(&AccountId::Number(1)).unwrap();
+        assert_eq!(first_account.total(), Decimal::from(2));
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].to_string(), "AccountId(1) not found");
+    }
+
+    #[test]
+    fn creates_accounts_on_leading_withdrawals_when_allowed() {
+        let csv = "type,client,tx,amount
+                        withdrawal,1,1,1";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let mut ledger = Ledger::with_options(LedgerOptions {
+            create_on_withdrawal: true,
+            ..Default::default()
+        });
+        let (accounts, errors) = process_csv_with_ledger(&mut ledger, csv);
+        let first_account = accounts.get(&AccountId::Number(1)).unwrap();
+        assert_eq!(first_account.available(), Decimal::ZERO);
+        assert_eq!(first_account.total(), Decimal::ZERO);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "TransactionId(1) for AccountId(1) can't withdraw $1 due to insufficient funds"
+        );
+    }
+
+    #[test]
+    fn reserves_part_of_every_deposit() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,100";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let mut ledger = Ledger::with_options(LedgerOptions {
+            deposit_reserve_pct: Some(Decimal::TEN),
+            ..Default::default()
+        });
+        let (mut accounts, errors) = process_csv_with_ledger(&mut ledger, csv);
+        let first_account = accounts.get_mut(&AccountId::Number(1)).unwrap();
+        assert_eq!(errors.len(), 0);
+        assert_eq!(first_account.available(), Decimal::from(90));
+        assert_eq!(first_account.held(), Decimal::TEN);
+        assert_eq!(first_account.reserved(), Decimal::TEN);
+        assert_eq!(first_account.total(), Decimal::ONE_HUNDRED);
+
+        first_account.release_reserve(Decimal::TEN).unwrap();
+        assert_eq!(first_account.available(), Decimal::ONE_HUNDRED);
+        assert_eq!(first_account.held(), Decimal::ZERO);
+        assert_eq!(first_account.reserved(), Decimal::ZERO);
+    }
+
+    #[test]
+    fn releases_reserved_funds_only() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,100
+                        deposit,1,2,50
+                        dispute,1,2,
+                        release_reserve,1,3,20
+                        release_reserve,1,4,4
+                        release_reserve,1,5,5";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let mut ledger = Ledger::with_options(LedgerOptions {
+            deposit_reserve_pct: Some(Decimal::TEN),
+            ..Default::default()
+        });
+        let (accounts, errors) = process_csv_with_ledger(&mut ledger, csv);
+        let first_account = accounts.get(&AccountId::Number(1)).unwrap();
+
+        // 15 were reserved and 50 are held by the dispute, which can't be released
+        assert_eq!(first_account.reserved(), Decimal::from(6));
+        assert_eq!(first_account.held(), Decimal::from(56));
+        assert_eq!(first_account.available(), Decimal::from(94));
+        assert_eq!(first_account.total(), Decimal::from(150));
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "Transaction #3 releases $20 but account #1 only has $15.0000 reserved"
+        );
+    }
+
+    #[test]
+    fn accrues_interest_on_held_funds_into_the_configured_balance() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,100
+                        deposit,1,2,50
+                        dispute,1,2,
+                        accrue_held_interest,1,3,";
+        let reader = || {
+            ReaderBuilder::new()
+                .has_headers(true)
+                .trim(Trim::All)
+                .flexible(true)
+                .from_reader(csv.as_bytes())
+        };
+        let accrue = |held_interest_to| {
+            let mut ledger = Ledger::with_options(LedgerOptions {
+                held_interest_pct: Some(Decimal::from(5)),
+                held_interest_to,
+                ..Default::default()
+            });
+            let (accounts, errors) = process_csv_with_ledger(&mut ledger, reader());
+            assert_eq!(errors.len(), 0);
+            accounts.get(&AccountId::Number(1)).unwrap().clone()
+        };
+
+        let credited_to_available = accrue(InterestDestination::Available);
+        assert_eq!(
+            credited_to_available.available(),
+            Decimal::from_str_exact("102.5").unwrap()
+        );
+        assert_eq!(credited_to_available.held(), Decimal::from(50));
+
+        // Interest credited to held goes into the reserve, the disputed funds are left untouched
+        let credited_to_held = accrue(InterestDestination::Held);
+        assert_eq!(credited_to_held.available(), Decimal::from(100));
+        assert_eq!(
+            credited_to_held.held(),
+            Decimal::from_str_exact("52.5").unwrap()
+        );
+        assert_eq!(
+            credited_to_held.reserved(),
+            Decimal::from_str_exact("2.5").unwrap()
+        );
+
+        let (_, errors) = process_csv(reader());
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "Transaction #3 accrues interest on held funds but no interest rate is configured"
+        );
+    }
+
+    #[test]
+    fn rejects_interest_overflowing_the_held_funds() {
+        let large = Decimal::MAX - Decimal::ONE_HUNDRED;
+        let row = |transaction_type, tx| {
+            Ok::<_, Error>(Transaction::new(
+                transaction_type,
+                AccountId::Number(1),
+                TransactionId(tx),
+            ))
+        };
+        let rows = vec![
+            row(TransactionType::Deposit(large, DepositState::Deposited), 1),
+            row(TransactionType::Dispute, 1),
+            row(TransactionType::AccrueHeldInterest, 2),
+        ];
+        // The command line caps the rate at 100%, but the library takes any rate
+        let mut ledger = Ledger::with_options(LedgerOptions {
+            held_interest_pct: Some(Decimal::from(200)),
+            ..Default::default()
+        });
+        let mut accounts = HashMap::new();
+        let errors = process_rows(&mut ledger, &mut accounts, rows, &mut Processing::default());
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "Transaction #2 for account #1 overflowed account balance"
+        );
+        assert_eq!(accounts[&AccountId::Number(1)].held(), large);
+    }
+
+    #[test]
+    fn withdraws_balances_rounded_on_ingest_down_to_zero() {
+        // Amounts are rounded when they are stored, so withdrawing 1 out of 0.99996 is accepted and
+        // leaves exactly zero
+        let csv = "type,client,tx,amount
+                        deposit,1,1,0.99996
+                        withdrawal,1,2,1";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let mut ledger = Ledger::default();
+        let (accounts, errors) = process_csv_with_ledger(&mut ledger, csv);
+        let options = Options {
+            format: OutputFormat::Json,
+            ..Default::default()
+        };
+        let mut output = vec![];
+        write_report(
+            &mut output,
+            &Report::from_accounts(&accounts),
+            &ledger,
+            &options,
+        )
+        .unwrap();
+
+        assert_eq!(errors.len(), 0);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "[\n  {\"client\":1,\"available\":\"0.0000\",\"held\":\"0.0000\",\"total\":\"0.0000\",\"locked\":false}\n]\n"
+        );
+    }
+
+    #[test]
+    fn flags_or_rejects_disputes_breaching_the_held_ratio() {
+        let input = "type,client,tx,amount
+                        deposit,1,1,10
+                        deposit,1,2,1
+                        deposit,1,3,8
+                        dispute,1,2,
+                        dispute,1,3,";
+        let process = |held_ratio_action| {
+            let csv = ReaderBuilder::new()
+                .has_headers(true)
+                .trim(Trim::All)
+                .flexible(true)
+                .from_reader(input.as_bytes());
+            let mut ledger = Ledger::with_options(LedgerOptions {
+                max_held_ratio: Some(Decimal::new(5, 1)),
+                held_ratio_action,
+                ..Default::default()
+            });
+            let mut accounts = HashMap::new();
+            let mut diagnostics = vec![];
+            let mut processing = Processing {
+                diagnostics: Some(&mut diagnostics),
+                ..Default::default()
+            };
+            process_rows(
+                &mut ledger,
+                &mut accounts,
+                csv.into_deserialize(),
+                &mut processing,
+            );
+            let held = accounts.get(&AccountId::Number(1)).unwrap().held();
+            let diagnostics = diagnostics
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>();
+            (held, diagnostics)
+        };
+
+        // Holding 1 out of 18 available is fine, while holding 9 out of 10 breaches the 0.5 ratio
+        assert_eq!(
+            process(HeldRatioAction::Warn),
+            (
+                Decimal::from(9),
+                vec!["line 6: [WARN] Transaction #3 pushed the held funds of account #1 over 0.5 times its available funds".to_string()]
+            )
+        );
+        assert_eq!(
+            process(HeldRatioAction::Reject),
+            (
+                Decimal::from(1),
+                vec!["line 6: [ERROR] Transaction #3 would push the held funds of account #1 over 0.5 times its available funds".to_string()]
+            )
+        );
+    }
+
+    #[test]
+    fn checks_the_held_ratio_of_near_max_balances() {
+        let large = Decimal::MAX - Decimal::ONE_HUNDRED;
+        let row = |transaction_type, client, tx| {
+            Ok::<_, Error>(Transaction::new(
+                transaction_type,
+                AccountId::Number(client),
+                TransactionId(tx),
+            ))
+        };
+        let rows = vec![
+            row(
+                TransactionType::Deposit(Decimal::ONE, DepositState::Deposited),
+                1,
+                1,
+            ),
+            row(
+                TransactionType::Deposit(large, DepositState::Deposited),
+                1,
+                2,
+            ),
+            row(TransactionType::Dispute, 1, 1),
+            row(
+                TransactionType::Deposit(large, DepositState::Deposited),
+                2,
+                3,
+            ),
+            row(
+                TransactionType::Withdrawal(large, DepositState::Deposited),
+                2,
+                4,
+            ),
+            row(TransactionType::Dispute, 2, 3),
+        ];
+        let mut ledger = Ledger::with_options(LedgerOptions {
+            max_held_ratio: Some(Decimal::from(2)),
+            ..Default::default()
+        });
+        let mut accounts = HashMap::new();
+        let mut diagnostics = vec![];
+        let mut processing = Processing {
+            diagnostics: Some(&mut diagnostics),
+            ..Default::default()
+        };
+        let errors = process_rows(&mut ledger, &mut accounts, rows, &mut processing);
+
+        // Twice the available funds of client 1 overflows, which no held amount can be over,
+        // while twice the negative available funds of client 2 is breached
+        assert!(errors.is_empty());
+        assert_eq!(
+            diagnostics
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            vec!["line 7: [WARN] Transaction #3 pushed the held funds of account #2 over 2 times its available funds"]
+        );
+    }
+
+    #[test]
+    fn rejects_withdrawal_disputes_on_overdrawn_accounts() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,10
+                        withdrawal,1,2,8
+                        dispute,1,1,
+                        dispute,1,2,";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let (accounts, errors) = process_csv(csv);
+        let first_account = accounts.get(&AccountId::Number(1)).unwrap();
+
+        // The disputed deposit overdraws the account, and the withdrawal dispute leaves it as is
+        assert_eq!(first_account.available(), Decimal::from(-8));
+        assert_eq!(first_account.held(), Decimal::TEN);
+        assert_eq!(first_account.total(), Decimal::TWO);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "Transaction #2 is a withdrawal and cannot be disputed"
+        );
+    }
+
+    #[test]
+    fn holds_withdrawal_disputes_on_overdrawn_accounts() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,10
+                        withdrawal,1,2,8
+                        dispute,1,1,
+                        dispute,1,2,";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let mut ledger = Ledger::with_options(LedgerOptions {
+            dispute_withdrawals: true,
+            ..Default::default()
+        });
+        let (accounts, errors) = process_csv_with_ledger(&mut ledger, csv);
+        let first_account = accounts.get(&AccountId::Number(1)).unwrap();
+
+        // The disputed deposit overdraws the account, and the withdrawal dispute holds its funds
+        // without changing the available ones
+        assert_eq!(first_account.available(), Decimal::from(-8));
+        assert_eq!(first_account.held(), Decimal::from(18));
+        assert_eq!(first_account.total(), Decimal::TEN);
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn rejects_transactions_on_locked_accounts() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,10
+                        deposit,1,2,5
+                        dispute,1,1,
+                        chargeback,1,1,
+                        deposit,1,7,3";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let (accounts, errors) = process_csv(csv);
+        let first_account = accounts.get(&AccountId::Number(1)).unwrap();
+
+        assert!(first_account.locked());
+        assert_eq!(first_account.available(), Decimal::from(5));
+        assert_eq!(first_account.held(), Decimal::ZERO);
+        assert_eq!(first_account.total(), Decimal::from(5));
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "Account #1 is locked and cannot process transaction #7"
+        );
+    }
+
+    #[test]
+    fn blocks_withdrawals_during_the_deposit_cooldown() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,1000
+                        withdrawal,1,2,10
+                        deposit,1,3,5
+                        withdrawal,1,4,10";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let mut ledger = Ledger::with_options(LedgerOptions {
+            cooldown_min_deposit: Some(Decimal::from(500)),
+            cooldown_transactions: Some(2),
+            ..Default::default()
+        });
+        let (accounts, errors) = process_csv_with_ledger(&mut ledger, csv);
+        let first_account = accounts.get(&AccountId::Number(1)).unwrap();
+
+        // The first withdrawal comes right after the large deposit, the second one once the two
+        // transactions of the cooldown went by
+        assert_eq!(first_account.available(), Decimal::from(995));
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "Transaction #2 blocked by deposit cooldown"
+        );
+    }
+
+    #[test]
+    fn rejects_deposits_overflowing_the_balance() {
+        // Two deposits of more than half of the largest decimal don't fit in the balance
+        let large = Decimal::MAX / Decimal::TWO + Decimal::ONE;
+        let deposit = |client, tx, amount| {
+            Ok::<_, Error>(Transaction::new(
+                TransactionType::Deposit(amount, DepositState::Deposited),
+                AccountId::Number(client),
+                TransactionId(tx),
+            ))
+        };
+        let rows = vec![
+            deposit(1, 1, large),
+            deposit(2, 2, Decimal::from(5)),
+            deposit(1, 9, large),
+            deposit(2, 3, Decimal::TWO),
+        ];
+
+        let mut accounts = HashMap::new();
+        let errors = process_rows(
+            &mut Ledger::default(),
+            &mut accounts,
+            rows,
+            &mut Processing::default(),
+        );
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "Transaction #9 for account #1 overflowed account balance"
+        );
+        assert_eq!(accounts[&AccountId::Number(1)].total(), large);
+        assert_eq!(accounts[&AccountId::Number(2)].total(), Decimal::from(7));
+    }
+
+    #[test]
+    fn charges_back_without_locking_when_configured() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,10
+                        deposit,1,2,5
+                        dispute,1,2,
+                        chargeback,1,2,
+                        deposit,1,3,1";
+        let process = |no_lock_on_chargeback| {
+            let csv = ReaderBuilder::new()
+                .has_headers(true)
+                .trim(Trim::All)
+                .flexible(true)
+                .from_reader(csv.as_bytes());
+            let mut ledger = Ledger::with_options(LedgerOptions {
+                no_lock_on_chargeback,
+                ..Default::default()
+            });
+            let (accounts, errors) = process_csv_with_ledger(&mut ledger, csv);
+            (accounts[&AccountId::Number(1)].clone(), errors.len())
+        };
+
+        // The held funds are debited either way, but only the default locks the account and
+        // rejects the deposit after the chargeback
+        let (unlocked, errors) = process(true);
+        assert!(!unlocked.locked());
+        assert_eq!(unlocked.held(), Decimal::ZERO);
+        assert_eq!(unlocked.total(), Decimal::from(11));
+        assert_eq!(errors, 0);
+
+        let (locked, errors) = process(false);
+        assert!(locked.locked());
+        assert_eq!(locked.held(), Decimal::ZERO);
+        assert_eq!(locked.total(), Decimal::TEN);
+        assert_eq!(errors, 1);
+    }
+
+    #[test]
+    fn only_settles_transactions_under_dispute() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,10
+                        deposit,1,2,5
+                        resolve,1,1,
+                        chargeback,1,2,
+                        dispute,1,2,
+                        resolve,1,2,
+                        chargeback,1,2,";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let (accounts, errors) = process_csv(csv);
+        let first_account = accounts.get(&AccountId::Number(1)).unwrap();
+
+        // Neither the settlements without a dispute nor the one after the resolve move any funds
+        assert_eq!(first_account.available(), Decimal::from(15));
+        assert_eq!(first_account.held(), Decimal::ZERO);
+        assert!(!first_account.locked());
+        assert_eq!(errors.len(), 3);
+        assert_eq!(errors[0].to_string(), "Transaction #1 is not under dispute");
+        assert_eq!(errors[1].to_string(), "Transaction #2 is not under dispute");
+        assert_eq!(errors[2].to_string(), "Transaction #2 is not under dispute");
+    }
+
+    #[test]
+    fn rejects_a_second_chargeback() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,10
+                        deposit,1,2,5
+                        dispute,1,2,
+                        chargeback,1,2,
+                        chargeback,1,2,";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        // The account stays unlocked so the second chargeback reaches the ledger
+        let mut ledger = Ledger::with_options(LedgerOptions {
+            no_lock_on_chargeback: true,
+            ..Default::default()
+        });
+        let (accounts, errors) = process_csv_with_ledger(&mut ledger, csv);
+        let first_account = accounts.get(&AccountId::Number(1)).unwrap();
+
+        assert_eq!(first_account.available(), Decimal::TEN);
+        assert_eq!(first_account.held(), Decimal::ZERO);
+        assert_eq!(first_account.total(), Decimal::TEN);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].to_string(), "Transaction #2 is not under dispute");
+    }
+
+    #[test]
+    fn resumes_a_snapshotted_ledger() {
+        let reader = |csv: &'static str| {
+            ReaderBuilder::new()
+                .has_headers(true)
+                .trim(Trim::All)
+                .flexible(true)
+                .from_reader(csv.as_bytes())
+        };
+        let first_day = reader(
+            "type,client,tx,amount
+                        deposit,1,1,10
+                        deposit,1,2,5",
+        );
+        let second_day = reader(
+            "type,client,tx,amount
+                        dispute,1,1,
+                        deposit,1,2,5",
+        );
+
+        let mut accounts = HashMap::new();
+        let mut ledger = Ledger::default();
+        let errors = process_rows(
+            &mut ledger,
+            &mut accounts,
+            transactions(first_day, false),
+            &mut Processing::default(),
+        );
+        assert_eq!(errors.len(), 0);
+
+        let state = ledger.snapshot();
+        let mut resumed = Ledger::from_snapshot(state.clone(), LedgerOptions::default());
+        assert_eq!(resumed.snapshot(), state);
+        let errors = process_rows(
+            &mut resumed,
+            &mut accounts,
+            transactions(second_day, false),
+            &mut Processing::default(),
+        );
+        let first_account = accounts.get(&AccountId::Number(1)).unwrap();
+
+        // The dispute finds the deposit of the first day, which also can't be redelivered
+        assert_eq!(first_account.held(), Decimal::TEN);
+        assert_eq!(first_account.available(), Decimal::from(5));
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].to_string(), "TransactionId(2) already exists");
+    }
+
+    #[test]
+    fn round_trips_snapshots_of_named_clients() {
+        let reader = |csv: &'static str| {
+            ReaderBuilder::new()
+                .has_headers(true)
+                .trim(Trim::All)
+                .flexible(true)
+                .from_reader(csv.as_bytes())
+        };
+        let options = LedgerOptions {
+            cooldown_min_deposit: Some(Decimal::from(100)),
+            cooldown_transactions: Some(2),
+            ..Default::default()
+        };
+        let first_day = reader(
+            "type,client,tx,amount
+                        deposit,1,1,500
+                        deposit,01,2,5
+                        deposit,2,3,7",
+        );
+        let second_day = reader(
+            "type,client,tx,amount
+                        withdrawal,1,4,10
+                        dispute,01,2,
+                        dispute,1,1,",
+        );
+
+        let mut accounts = HashMap::new();
+        let mut ledger = Ledger::with_options(options);
+        let errors = process_rows(
+            &mut ledger,
+            &mut accounts,
+            transactions(first_day, true),
+            &mut Processing::default(),
+        );
+        assert_eq!(errors.len(), 0);
+
+        // Through JSON, whose object keys are strings, the name `1` stays apart from the number 1
+        let state = ledger.snapshot();
+        let json = serde_json::to_string(&state).unwrap();
+        let state = serde_json::from_str::<LedgerState>(&json).unwrap();
+        assert_eq!(state, ledger.snapshot());
+        let mut resumed = Ledger::from_snapshot(state, options);
+        let errors = process_rows(
+            &mut resumed,
+            &mut accounts,
+            transactions(second_day, true),
+            &mut Processing::default(),
+        );
+
+        // The cooldown of client `1` carries over, and both disputes find their named deposits
+        assert_eq!(
+            errors.iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+            ["Transaction #4 blocked by deposit cooldown"]
+        );
+        let held = |name| accounts[&AccountId::parse(name, true).unwrap()].held();
+        assert_eq!(held("1"), Decimal::from(500));
+        assert_eq!(held("01"), Decimal::from(5));
+
+        // Numeric ids come back as numbers rather than as the names of their JSON keys
+        let mut ledger = Ledger::with_options(options);
+        process_rows(
+            &mut ledger,
+            &mut HashMap::new(),
+            transactions(reader("type,client,tx,amount\ndeposit,1,1,500"), false),
+            &mut Processing::default(),
+        );
+        let json = serde_json::to_string(&ledger.snapshot()).unwrap();
+        assert_eq!(
+            serde_json::from_str::<LedgerState>(&json).unwrap(),
+            ledger.snapshot()
+        );
+    }
+
+    #[test]
+    fn rejects_float_artifacts_when_configured() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,0.30000000000000004
+                        deposit,1,2,0.3";
+        let process = |reject_float_artifacts| {
+            let csv = ReaderBuilder::new()
+                .has_headers(true)
+                .trim(Trim::All)
+                .flexible(true)
+                .from_reader(csv.as_bytes());
+            let mut ledger = Ledger::with_options(LedgerOptions {
+                reject_float_artifacts,
+                ..Default::default()
+            });
+            let (accounts, errors) = process_csv_with_ledger(&mut ledger, csv);
+            (accounts[&AccountId::Number(1)].total(), errors)
+        };
+
+        let (total, errors) = process(true);
+        assert_eq!(total.to_string(), "0.3000");
+        assert_eq!(
+            errors.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            ["Transaction #1 amount 0.30000000000000004 has more than 15 decimal places, which looks like a float serialization artifact"]
+        );
+
+        // By default the artifact is rounded like any other precise amount
+        let (total, errors) = process(false);
+        assert_eq!(total.to_string(), "0.6000");
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn resolves_disputes_on_locked_accounts() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,10
+                        deposit,1,2,5
+                        dispute,1,1,
+                        dispute,1,2,
+                        chargeback,1,1,
+                        resolve,1,2,
+                        withdrawal,1,3,1";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let (accounts, errors) = process_csv(csv);
+        let account = &accounts[&AccountId::Number(1)];
+
+        // The resolve releases the funds of the second dispute, which still can't be withdrawn
+        assert!(account.locked());
+        assert_eq!(account.available(), Decimal::from(5));
+        assert_eq!(account.held(), Decimal::ZERO);
+        assert_eq!(account.total(), Decimal::from(5));
+        assert_eq!(
+            errors.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            ["Account #1 is locked and cannot process transaction #3"]
+        );
+    }
+}
@@ -0,0 +1,730 @@
+pub mod account;
+mod checkpoint;
+mod chunked;
+mod diagnostics;
+mod diff;
+mod generate;
+#[cfg(feature = "http")]
+mod http;
+mod jsonl;
+pub mod ledger;
+mod line_guard;
+mod manifest;
+mod money;
+mod options;
+mod parallel;
+mod processing;
+mod report;
+mod schema;
+mod store;
+pub mod transaction;
+
+pub use crate::account::{Account, AccountId, BalanceOverflow};
+pub use crate::diagnostics::{Diagnostic, Severity};
+use crate::diff::DiffOptions;
+use crate::generate::GeneratorOptions;
+pub use crate::ledger::Ledger;
+use crate::line_guard::LineGuard;
+use crate::manifest::{Fingerprinting, Manifest};
+pub use crate::options::Options;
+use crate::options::{InputFormat, STDIN_PATH};
+use crate::processing::{process_rows, Checkpointing, LocatedRow, Processing};
+use crate::report::Tee;
+pub use crate::report::{Report, ReportStats};
+pub use crate::store::AccountStore;
+pub use crate::transaction::Transaction;
+use crate::transaction::{check_field_count, NamedTransaction};
+use anyhow::{bail, Error};
+use csv::{Reader, ReaderBuilder, StringRecord, Trim};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, Read, Write};
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::rc::Rc;
+use std::thread;
+use std::time::Instant;
+
+// Runs the command line tool with the specified arguments, the first one being the program name
+pub fn run(args: &[String]) -> Result<(), Error> {
+    if args.get(1).map(String::as_str) == Some("generate") {
+        let options = GeneratorOptions::parse(&args[2..])?;
+        let mut out = BufWriter::new(io::stdout().lock());
+        generate::generate(&mut out, &options)?;
+        out.flush()?;
+        return Ok(());
+    }
+    if args.get(1).map(String::as_str) == Some("diff") {
+        let options = DiffOptions::parse(&args[2..])?;
+        let differences = diff::diff_reports(
+            File::open(&options.left)?,
+            File::open(&options.right)?,
+            options.scale,
+        )?;
+        differences
+            .iter()
+            .for_each(|difference| println!("{}", difference));
+        if !differences.is_empty() {
+            bail!("Reports differ in {} values", differences.len());
+        }
+        return Ok(());
+    }
+
+    let mut options = Options::parse(args)?;
+    // Describes the output as it's written when piped, regardless of the terminal defaults
+    if options.emit_schema {
+        schema::write_schema(&mut io::stdout().lock(), &options)?;
+        return Ok(());
+    }
+    if let Some(rows) = options.validate_only_schema {
+        let mut problems = vec![];
+        for input in &options.inputs {
+            problems.extend(schema::validate_schema(
+                open_input(&input.path)?,
+                rows,
+                &options,
+            )?);
+        }
+        problems.iter().for_each(|problem| eprintln!("{}", problem));
+        if !problems.is_empty() {
+            bail!("Found {} schema problems", problems.len());
+        }
+        return Ok(());
+    }
+    options.apply_terminal_defaults(io::stdout().is_terminal());
+
+    let mut ledger = Ledger::with_options(options.ledger);
+    for client in options.watched_clients.iter().copied() {
+        ledger.on_account_change(client, move |transaction, account| {
+            eprintln!(
+                "{:?} after {:?}: available {}, held {}, total {}, locked {}",
+                client,
+                transaction.transaction_id,
+                account.available(),
+                account.held(),
+                account.total(),
+                account.locked()
+            )
+        });
+    }
+    let history = Rc::new(RefCell::new(vec![]));
+    if let Some(client) = options.explained_client {
+        let recorded = history.clone();
+        ledger.on_account_change(client, move |transaction, account| {
+            recorded.borrow_mut().push((*transaction, account.clone()))
+        });
+    }
+
+    let mut accounts = options.accounts_store.new_store();
+    let processed_rows = match &options.resume {
+        Some(path) => checkpoint::read_checkpoint(Path::new(path), &mut ledger, &mut *accounts)?,
+        None => 0,
+    };
+    let mut audit_trail = match &options.audit_trail {
+        Some(path) => {
+            let mut audit_trail = BufWriter::new(File::create(path)?);
+            report::write_audit_header(&mut audit_trail)?;
+            Some(audit_trail)
+        }
+        None => None,
+    };
+    let mut dispute_events = match &options.dispute_events {
+        Some(path) => {
+            let mut dispute_events = BufWriter::new(File::create(path)?);
+            report::write_dispute_events_header(&mut dispute_events)?;
+            Some(dispute_events)
+        }
+        None => None,
+    };
+    let mut diagnostics = vec![];
+    let started = Instant::now();
+    let mut processing = Processing {
+        processed_rows,
+        checkpointing: options.checkpoint.as_ref().map(|path| Checkpointing {
+            path: Path::new(path),
+            every: options
+                .checkpoint_every
+                .unwrap_or(DEFAULT_CHECKPOINT_INTERVAL),
+        }),
+        audit_trail: audit_trail
+            .as_mut()
+            .map(|audit_trail| audit_trail as &mut dyn Write),
+        dispute_events: dispute_events
+            .as_mut()
+            .map(|dispute_events| dispute_events as &mut dyn Write),
+        deadline: options
+            .max_processing_time
+            .and_then(|budget| started.checked_add(budget)),
+        diagnostics: (options.diagnostics
+            || options.errors_file.is_some()
+            || options.manifest.is_some())
+        .then_some(&mut diagnostics),
+        ..Default::default()
+    };
+    // Inputs share the ledger and accounts, so a row can reference transactions of earlier inputs
+    let mut rows = 0;
+    let mut input_fingerprints = vec![];
+    for input_source in &options.inputs {
+        let mut input = open_input(&input_source.path)?;
+        let mut input_fingerprint = Rc::default();
+        if options.manifest.is_some() {
+            let fingerprinting = Fingerprinting::new(input);
+            input_fingerprint = fingerprinting.fingerprint();
+            input = Box::new(BufReader::new(fingerprinting));
+        }
+        processing.source = (options.inputs.len() > 1).then_some(input_source.path.as_str());
+        match (input_source.format, options.parse_threads) {
+            (InputFormat::Jsonl, _) => {
+                let rows = jsonl::jsonl_rows(input, options.string_client_ids);
+                process_rows(&mut ledger, &mut *accounts, rows, &mut processing)
+            }
+            (InputFormat::Csv, Some(threads)) => {
+                let mut buffered = vec![];
+                input.read_to_end(&mut buffered)?;
+                let rows = chunked::parse_chunked(&buffered, threads, options.string_client_ids);
+                process_rows(&mut ledger, &mut *accounts, rows, &mut processing)
+            }
+            (InputFormat::Csv, None) => {
+                let max_line_length = options.max_line_length.unwrap_or(DEFAULT_MAX_LINE_LENGTH);
+                let rows = guarded_rows(
+                    LineGuard::new(input, max_line_length),
+                    options.string_client_ids,
+                );
+                process_rows(&mut ledger, &mut *accounts, rows, &mut processing)
+            }
+        };
+        rows += processing.rows;
+        input_fingerprints.push((input_source.path.as_str(), input_fingerprint));
+        if processing.budget_exceeded {
+            break;
+        }
+    }
+    let applied = processing.applied;
+    let budget_exceeded = processing.budget_exceeded;
+    if let Some(audit_trail) = &mut audit_trail {
+        audit_trail.flush()?;
+    }
+    if let Some(dispute_events) = &mut dispute_events {
+        dispute_events.flush()?;
+    }
+    if options.diagnostics {
+        diagnostics
+            .iter()
+            .for_each(|diagnostic| eprintln!("{}", diagnostic));
+    }
+    if let Some(path) = &options.errors_file {
+        let mut errors_file = BufWriter::new(File::create(path)?);
+        diagnostics::write_errors(&mut errors_file, &diagnostics, options.errors_format)?;
+        errors_file.flush()?;
+    }
+    if let Some(path) = &options.manifest {
+        let mut manifest_file = BufWriter::new(File::create(path)?);
+        let inputs = input_fingerprints
+            .iter()
+            .map(|(path, fingerprint)| (*path, *fingerprint.borrow()))
+            .collect::<Vec<_>>();
+        let manifest = Manifest {
+            inputs: &inputs,
+            rows,
+            transactions_applied: applied,
+            diagnostics: &diagnostics,
+            accounts: &*accounts,
+        };
+        manifest::write_manifest(&mut manifest_file, &manifest)?;
+        manifest_file.flush()?;
+    }
+
+    let mut sinks: Vec<Box<dyn Write>> = vec![Box::new(io::stdout().lock())];
+    for output in &options.outputs {
+        sinks.push(Box::new(BufWriter::new(File::create(output)?)));
+    }
+    let mut out = Tee::new(sinks);
+    // Only the accounts are rendered, so the errors are only gathered when another option needs them
+    diagnostics.retain(|diagnostic| diagnostic.severity == Severity::Error);
+    let report = Report {
+        errors: diagnostics,
+        stats: ReportStats {
+            rows_read: rows,
+            transactions_applied: applied,
+        },
+        ..Report::from_accounts(&*accounts)
+    };
+    report::write_report(&mut out, &report, &ledger, &options)?;
+    out.flush()?;
+    if options.transactions_applied {
+        eprintln!("rows_read: {}, transactions_applied: {}", rows, applied);
+    }
+    if options.summary {
+        report::write_summary(
+            &mut io::stderr().lock(),
+            &report::Summary::from_accounts(&*accounts)?,
+        )?;
+    }
+    if options.rounding_report {
+        report::write_rounding_report(
+            &mut io::stderr().lock(),
+            &report::RoundingReport::from_accounts(&*accounts, &options)?,
+        )?;
+    }
+    if options.report_dangling_disputes {
+        report::write_dangling_disputes(&mut io::stderr().lock(), &ledger)?;
+    }
+    if let Some(client) = options.explained_client {
+        report::write_account_history(
+            &mut io::stderr().lock(),
+            client,
+            &history.borrow(),
+            accounts.account(&client),
+            &ledger,
+        )?;
+    }
+    // The accounts processed so far were still reported, but the run didn't get through its inputs
+    if budget_exceeded {
+        bail!("processing time budget exceeded");
+    }
+    Ok(())
+}
+
+const DEFAULT_CHECKPOINT_INTERVAL: u64 = 10_000;
+const DEFAULT_MAX_LINE_LENGTH: usize = 1 << 20;
+
+// The input is either stdin, a local path or, with the `http` feature, an http:// or https:// URL
+fn open_input(path: &str) -> Result<Box<dyn BufRead>, Error> {
+    if path == STDIN_PATH {
+        return Ok(Box::new(io::stdin().lock()));
+    }
+    if path.starts_with("http://") || path.starts_with("https://") {
+        #[cfg(feature = "http")]
+        return Ok(Box::new(http::fetch(path)?));
+        #[cfg(not(feature = "http"))]
+        bail!("Reading {} requires building with the http feature", path);
+    }
+    Ok(Box::new(BufReader::new(File::open(path)?)))
+}
+
+// Processes a CSV input with the default ledger rules, returning the final state of every account
+// along with the errors of the rows which were rejected
+pub fn process_reader(input: impl Read) -> (HashMap<AccountId, Account>, Vec<Error>) {
+    process_reader_into(input)
+}
+
+// Same as `process_reader`, applying the transactions of different clients on separate threads.
+// The accounts end up the same, while the errors are listed client by client rather than in input
+// order
+pub fn process_reader_parallel(input: impl Read) -> (HashMap<AccountId, Account>, Vec<Error>) {
+    let shards = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    parallel::process_sharded(transactions(input_reader(input), false), shards)
+}
+
+// Same as `process_reader`, keeping the accounts in the specified store such as a BTreeMap
+pub fn process_reader_into<S: AccountStore + Default>(input: impl Read) -> (S, Vec<Error>) {
+    let mut accounts = S::default();
+    let errors = process_rows(
+        &mut Ledger::default(),
+        &mut accounts,
+        transactions(input_reader(input), false),
+        &mut Processing::default(),
+    );
+    (accounts, errors)
+}
+
+// Same as `process_reader`, returning the accounts sorted by client along with the errors of every
+// rejected row and the counts of rows read and transactions applied
+pub fn process_report(input: impl Read) -> Report {
+    let mut accounts = HashMap::new();
+    let mut diagnostics = vec![];
+    let mut processing = Processing {
+        diagnostics: Some(&mut diagnostics),
+        ..Default::default()
+    };
+    // No line is too long for the library, the guard only tells the lines of the rows
+    process_rows(
+        &mut Ledger::default(),
+        &mut accounts,
+        guarded_rows(LineGuard::new(BufReader::new(input), usize::MAX), false),
+        &mut processing,
+    );
+    let stats = ReportStats {
+        rows_read: processing.rows,
+        transactions_applied: processing.applied,
+    };
+    diagnostics.retain(|diagnostic| diagnostic.severity == Severity::Error);
+    Report {
+        errors: diagnostics,
+        stats,
+        ..Report::from_accounts(&accounts)
+    }
+}
+
+fn input_reader<R: Read>(input: R) -> Reader<R> {
+    ReaderBuilder::new()
+        .has_headers(true)
+        .trim(Trim::All) // Supports arbitrary blank spaces between columns
+        .flexible(true) // Allows parsing of differently sized rows
+        .from_reader(input)
+}
+
+// Deserializes the rows of the input, keeping the client ids as text with --string-client-ids.
+// The number of fields of every row is checked against its type first, since the flexible reader
+// would otherwise ignore extra fields and leave missing ones empty
+fn transactions<'a, R: Read + 'a>(
+    mut reader: Reader<R>,
+    string_client_ids: bool,
+) -> impl Iterator<Item = Result<Transaction, Error>> + 'a {
+    // Like the CSV deserializer, falls back to positional fields if the header can't be read
+    let headers = reader.headers().ok().cloned();
+    reader.into_records().map(move |record| {
+        let record = record?;
+        if let Some(headers) = &headers {
+            check_field_count(&record, headers)?;
+        }
+        deserialize_record(&record, headers.as_ref(), string_client_ids)
+    })
+}
+
+// Deserializes a single row, whose fields are mapped to the columns of the header if there is one
+fn deserialize_record(
+    record: &StringRecord,
+    headers: Option<&StringRecord>,
+    string_client_ids: bool,
+) -> Result<Transaction, Error> {
+    let transaction = if string_client_ids {
+        record.deserialize::<NamedTransaction>(headers)?.0
+    } else {
+        record.deserialize(headers)?
+    };
+    Ok(transaction)
+}
+
+// Rows replaced by the guard are reported as too long instead of failing to parse. The CSV reader
+// skips blank lines, so the lines of the rows are the ones the guard counted
+fn guarded_rows<'a, R: BufRead + 'a>(
+    guard: LineGuard<R>,
+    string_client_ids: bool,
+) -> impl Iterator<Item = LocatedRow> + 'a {
+    let oversized = guard.oversized();
+    let shifted = guard.shifted();
+    let mut blank_lines = 0;
+    (1..)
+        .zip(transactions(input_reader(guard), string_client_ids))
+        .map(move |(row_index, row)| {
+            if let Some(line) = shifted.borrow_mut().remove(&row_index) {
+                blank_lines = line - row_index - 1;
+            }
+            LocatedRow {
+                line: row_index + 1 + blank_lines,
+                row: match oversized.borrow_mut().remove(&row_index) {
+                    Some(too_long) => Err(Error::from(too_long)),
+                    None => row,
+                },
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::chunked::parse_chunked;
+    use crate::ledger::Ledger;
+    use crate::line_guard::LineGuard;
+    use crate::processing::tests::process_csv;
+    use crate::processing::{process_rows, LocatedRow, Processing};
+    use crate::{guarded_rows, AccountId};
+    use anyhow::Error;
+    use csv::{ReaderBuilder, Trim};
+    use rust_decimal::Decimal;
+    use std::collections::HashMap;
+    use std::io::Write;
+    use std::path::Path;
+
+    #[test]
+    fn process_csv_parses_file_correctly() {
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_path(Path::new("tests/basic.csv"))
+            .unwrap();
+        let (accounts, errors) = process_csv(csv);
+        let (first_account, second_account) = (
+            accounts.get(&AccountId::Number(1)).unwrap(),
+            accounts.get(&AccountId::Number(2)).unwrap(),
+        );
+        assert_eq!(
+            first_account.total(),
+            Decimal::from_str_exact("1.5").unwrap()
+        );
+        assert_eq!(
+            second_account.total(),
+            Decimal::from_str_exact("2").unwrap()
+        );
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "TransactionId(5) for AccountId(2) can't withdraw $3 due to insufficient funds"
+        );
+    }
+
+    #[test]
+    fn parses_csv_with_parsing_errors_correctly() {
+        let csv = "type,client,tx,amount
+                        invalid,0
+                        unknown,1,1
+                        deposit,1,1,-1.001
+                        deposit,1,1,
+                        deposit,1,1,1.0001
+                        deposit, 2, 2, 3.3";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let (accounts, errors) = process_csv(csv);
+        let (first_account, second_account) = (
+            accounts.get(&AccountId::Number(1)).unwrap(),
+            accounts.get(&AccountId::Number(2)).unwrap(),
+        );
+        assert_eq!(
+            first_account.total(),
+            Decimal::from_str_exact("1.0001").unwrap()
+        );
+        assert_eq!(
+            second_account.total(),
+            Decimal::from_str_exact("3.3").unwrap()
+        );
+        assert_eq!(errors.len(), 4);
+        assert_eq!(
+            errors[0].to_string(),
+            "CSV deserialize error: record 1 (line: 2, byte: 22): expected field, but got end of row"
+        );
+        assert_eq!(
+            errors[1].to_string(),
+            "CSV deserialize error: record 2 (line: 3, byte: 56): unknown is an unknown type"
+        );
+        assert_eq!(
+            errors[2].to_string(),
+            "CSV deserialize error: record 3 (line: 4, byte: 92): Transaction requires a positive amount but was -1.001"
+        );
+        assert_eq!(
+            errors[3].to_string(),
+            "CSV deserialize error: record 4 (line: 5, byte: 135): Transaction requires a defined amount"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn processes_transactions_written_into_a_fifo() {
+        let path = std::env::temp_dir().join(format!("transactions_{}.fifo", std::process::id()));
+        let status = std::process::Command::new("mkfifo")
+            .arg(&path)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        // Opening a FIFO blocks until the other end is opened too, so the writer runs on its own
+        // thread and the reader sees EOF once it's done and closes its end
+        let writer_path = path.clone();
+        let writer = std::thread::spawn(move || {
+            let mut fifo = std::fs::OpenOptions::new()
+                .write(true)
+                .open(writer_path)
+                .unwrap();
+            for row in [
+                "type,client,tx,amount",
+                "deposit,1,1,2",
+                "withdrawal,1,2,0.5",
+                "deposit,2,3,1",
+            ] {
+                writeln!(fifo, "{}", row).unwrap();
+                fifo.flush().unwrap();
+            }
+        });
+
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_path(&path)
+            .unwrap();
+        let (accounts, errors) = process_csv(csv);
+        writer.join().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(errors.len(), 0);
+        assert_eq!(
+            accounts.get(&AccountId::Number(1)).unwrap().available(),
+            Decimal::from_str_exact("1.5").unwrap()
+        );
+        assert_eq!(
+            accounts.get(&AccountId::Number(2)).unwrap().available(),
+            Decimal::from(1)
+        );
+    }
+
+    #[test]
+    fn diagnostics_count_the_blank_lines_skipped_by_the_reader() {
+        let input = "type,client,tx,amount\ndeposit,1,1,1.00005\n\nwithdrawal,1,2,5\n\n\ndeposit,1,3,abc\ndispute,1,7,\n";
+        let diagnose = |rows: Vec<LocatedRow>| {
+            let mut diagnostics = vec![];
+            let mut processing = Processing {
+                diagnostics: Some(&mut diagnostics),
+                ..Default::default()
+            };
+            process_rows(
+                &mut Ledger::default(),
+                &mut HashMap::new(),
+                rows,
+                &mut processing,
+            );
+            diagnostics
+                .iter()
+                .map(|diagnostic| {
+                    diagnostic
+                        .to_string()
+                        .split(']')
+                        .next()
+                        .unwrap()
+                        .to_string()
+                })
+                .collect::<Vec<_>>()
+        };
+        let expected = vec![
+            "line 2: [WARN",
+            "line 4: [ERROR",
+            "line 7: [ERROR",
+            "line 8: [ERROR",
+        ];
+
+        let rows = guarded_rows(LineGuard::new(input.as_bytes(), 100), false).collect();
+        assert_eq!(diagnose(rows), expected);
+        // The last row doesn't need a line break
+        let rows = guarded_rows(LineGuard::new(input.trim_end().as_bytes(), 100), false).collect();
+        assert_eq!(diagnose(rows), expected);
+        // Chunks parsed on their own still count the lines of the chunks before them
+        assert_eq!(
+            diagnose(parse_chunked(input.as_bytes(), 4, false)),
+            expected
+        );
+    }
+
+    #[test]
+    fn rejects_lines_over_the_maximum_length() {
+        let long_amount = "1".repeat(100);
+        let csv = format!(
+            "type,client,tx,amount\ndeposit,1,1,2\ndeposit,1,2,{}\n\ndeposit,1,3,1.5\r\ndeposit,1,4,{}",
+            long_amount, long_amount
+        );
+        let guard = LineGuard::new(csv.as_bytes(), 25);
+
+        let mut accounts = HashMap::new();
+        let errors = process_rows(
+            &mut Ledger::default(),
+            &mut accounts,
+            guarded_rows(guard, false),
+            &mut Processing::default(),
+        );
+
+        assert_eq!(
+            accounts.get(&AccountId::Number(1)).unwrap().total(),
+            Decimal::new(35, 1)
+        );
+        assert_eq!(
+            errors.iter().map(Error::to_string).collect::<Vec<_>>(),
+            vec![
+                "Line 3 is longer than the maximum of 25 bytes",
+                "Line 6 is longer than the maximum of 25 bytes",
+            ]
+        );
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn reads_the_input_from_an_http_url() {
+        use crate::open_input;
+        use std::io::{BufRead, BufReader, Read};
+        use std::net::TcpListener;
+
+        // Serves a single response to the first connection, after reading the request headers
+        let serve = |response: String| {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("http://{}/daily.csv", listener.local_addr().unwrap());
+            std::thread::spawn(move || {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                while request.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+                stream.write_all(response.as_bytes()).unwrap();
+            });
+            url
+        };
+
+        let url = serve(
+            "HTTP/1.0 200 OK\r\nContent-Type: text/csv\r\n\r\n\
+            type,client,tx,amount\ndeposit,1,1,2\ndeposit,1,2,1.5\nwithdrawal,1,3,5\n"
+                .to_string(),
+        );
+        let guard = LineGuard::new(open_input(&url).unwrap(), 100);
+        let mut accounts = HashMap::new();
+        let errors = process_rows(
+            &mut Ledger::default(),
+            &mut accounts,
+            guarded_rows(guard, false),
+            &mut Processing::default(),
+        );
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            accounts.get(&AccountId::Number(1)).unwrap().total(),
+            Decimal::new(35, 1)
+        );
+
+        // Redirects are followed, and chunked bodies decoded
+        let target = serve(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n\
+            1e\r\ntype,client,tx,amount\ndeposit,\r\n6\r\n1,1,2\n\r\n0\r\n\r\n"
+                .to_string(),
+        );
+        let url = serve(format!(
+            "HTTP/1.0 301 Moved Permanently\r\nLocation: {}\r\n\r\n",
+            target
+        ));
+        let mut body = String::new();
+        open_input(&url).unwrap().read_to_string(&mut body).unwrap();
+        assert_eq!(body, "type,client,tx,amount\ndeposit,1,1,2\n");
+
+        let url = serve("HTTP/1.0 404 Not Found\r\n\r\n".to_string());
+        assert_eq!(
+            open_input(&url).err().unwrap().to_string(),
+            format!("{} responded with 404 Not Found", url)
+        );
+        let url = serve("HTTP/1.0 204 No Content\r\n\r\n".to_string());
+        assert_eq!(
+            open_input(&url).err().unwrap().to_string(),
+            format!("{} responded with 204 No Content", url)
+        );
+
+        // A server speaking plain HTTP fails the TLS handshake of an https:// URL
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("https://{}/daily.csv", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream
+                .write_all(b"HTTP/1.0 400 Bad Request\r\n\r\n")
+                .unwrap();
+        });
+        assert_eq!(
+            open_input(&url).err().unwrap().to_string(),
+            format!("Cannot establish a TLS connection to {}", url)
+        );
+        assert_eq!(
+            open_input("http://[::1/daily.csv")
+                .err()
+                .unwrap()
+                .to_string(),
+            "http://[::1/daily.csv is not a valid http:// or https:// URL"
+        );
+    }
+}
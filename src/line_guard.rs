@@ -0,0 +1,137 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Read};
+use std::rc::Rc;
+
+// Stand-in for the rows which were too long, so the CSV reader still counts them as a row
+const OVERSIZED_ROW: &[u8] = b"oversized\n";
+
+#[derive(Debug, thiserror::Error)]
+#[error("Line {line} is longer than the maximum of {max} bytes")]
+pub(crate) struct LineTooLong {
+    pub line: u64,
+    pub max: usize,
+}
+
+// Rows replaced by the guard, keyed by their index as counted by the CSV reader
+pub(crate) type OversizedRows = Rc<RefCell<HashMap<u64, LineTooLong>>>;
+
+// Rows following blank lines, keyed by their index as counted by the CSV reader, with their line
+pub(crate) type ShiftedRows = Rc<RefCell<HashMap<u64, u64>>>;
+
+// Reader passing the input through line by line, which never buffers more than `max` bytes of a
+// single line. Longer lines are discarded as they're read, before the CSV reader gets to parse
+// them, and replaced by a placeholder row recorded in `oversized` along with the original line
+pub(crate) struct LineGuard<R> {
+    input: R,
+    max: usize,
+    line: Vec<u8>,
+    position: usize,
+    // Lines read so far, including the header
+    lines: u64,
+    // Non empty lines read after the header, which are the ones the CSV reader yields as rows
+    rows: u64,
+    oversized: OversizedRows,
+    // Whether blank lines were read since the last row
+    after_blank_lines: bool,
+    shifted: ShiftedRows,
+}
+
+impl<R: BufRead> LineGuard<R> {
+    pub fn new(input: R, max: usize) -> Self {
+        LineGuard {
+            input,
+            max,
+            line: vec![],
+            position: 0,
+            lines: 0,
+            rows: 0,
+            oversized: OversizedRows::default(),
+            after_blank_lines: false,
+            shifted: ShiftedRows::default(),
+        }
+    }
+
+    pub fn oversized(&self) -> OversizedRows {
+        self.oversized.clone()
+    }
+
+    pub fn shifted(&self) -> ShiftedRows {
+        self.shifted.clone()
+    }
+
+    // Loads the next line into the buffer, returning false once the input is exhausted
+    fn next_line(&mut self) -> io::Result<bool> {
+        self.line.clear();
+        self.position = 0;
+        // Two extra bytes leave room for a `\r\n` line break after a line of exactly `max` bytes
+        let read = (&mut self.input)
+            .take((self.max as u64).saturating_add(2))
+            .read_until(b'\n', &mut self.line)?;
+        if read == 0 {
+            return Ok(false);
+        }
+        self.lines += 1;
+
+        let content = self.line.strip_suffix(b"\n").unwrap_or(&self.line);
+        let content = content.strip_suffix(b"\r").unwrap_or(content);
+        let oversized = content.len() > self.max;
+        if self.lines > 1 && !content.is_empty() {
+            self.rows += 1;
+            if self.after_blank_lines {
+                self.after_blank_lines = false;
+                self.shifted.borrow_mut().insert(self.rows, self.lines);
+            }
+        } else if self.lines > 1 {
+            self.after_blank_lines = true;
+        }
+        if oversized {
+            if !self.line.ends_with(b"\n") {
+                self.skip_line()?;
+            }
+            self.oversized.borrow_mut().insert(
+                self.rows,
+                LineTooLong {
+                    line: self.lines,
+                    max: self.max,
+                },
+            );
+            self.line.clear();
+            self.line.extend_from_slice(OVERSIZED_ROW);
+        }
+        Ok(true)
+    }
+
+    // Discards the rest of the current line without buffering it
+    fn skip_line(&mut self) -> io::Result<()> {
+        loop {
+            let available = self.input.fill_buf()?;
+            if available.is_empty() {
+                return Ok(());
+            }
+            match available.iter().position(|byte| *byte == b'\n') {
+                Some(line_break) => {
+                    self.input.consume(line_break + 1);
+                    return Ok(());
+                }
+                None => {
+                    let skipped = available.len();
+                    self.input.consume(skipped);
+                }
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Read for LineGuard<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.line.len() && !self.next_line()? {
+            return Ok(0);
+        }
+        let remaining = &self.line[self.position..];
+        let read = remaining.len().min(buf.len());
+        buf[..read].copy_from_slice(&remaining[..read]);
+        self.position += read;
+        Ok(read)
+    }
+}
@@ -0,0 +1,222 @@
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::store::AccountStore;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+// 64 bit FNV-1a, which is stable across platforms and releases so manifests from different runs
+// can be compared
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Fingerprint {
+    pub bytes: u64,
+    pub hash: u64,
+}
+
+impl Default for Fingerprint {
+    fn default() -> Self {
+        Fingerprint {
+            bytes: 0,
+            hash: FNV_OFFSET_BASIS,
+        }
+    }
+}
+
+impl Fingerprint {
+    pub fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.hash ^= u64::from(*byte);
+            self.hash = self.hash.wrapping_mul(FNV_PRIME);
+        }
+        self.bytes += data.len() as u64;
+    }
+}
+
+// Reader which fingerprints everything read through it, so the input is hashed while it's streamed
+// instead of being read twice, which wouldn't even be possible for pipes and URLs
+pub(crate) struct Fingerprinting<R> {
+    input: R,
+    fingerprint: Rc<RefCell<Fingerprint>>,
+}
+
+impl<R: Read> Fingerprinting<R> {
+    pub fn new(input: R) -> Self {
+        Fingerprinting {
+            input,
+            fingerprint: Rc::default(),
+        }
+    }
+
+    pub fn fingerprint(&self) -> Rc<RefCell<Fingerprint>> {
+        self.fingerprint.clone()
+    }
+}
+
+impl<R: Read> Read for Fingerprinting<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.input.read(buf)?;
+        self.fingerprint.borrow_mut().update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+// Hash of the final state of every account, independent of the order accounts are stored or
+// reported in
+pub(crate) fn state_hash(accounts: &(impl AccountStore + ?Sized)) -> u64 {
+    let mut accounts = accounts.accounts().collect::<Vec<_>>();
+    accounts.sort_by_key(|(account_id, _)| **account_id);
+    let mut fingerprint = Fingerprint::default();
+    for (account_id, account) in accounts {
+        fingerprint.update(
+            format!(
+                "{},{},{},{},{}\n",
+                account_id,
+                account.available(),
+                account.held(),
+                account.total(),
+                account.locked()
+            )
+            .as_bytes(),
+        );
+    }
+    fingerprint.hash
+}
+
+// Everything needed to audit a run after the fact
+pub(crate) struct Manifest<'a> {
+    // Path and fingerprint of every input, in the order they were processed
+    pub inputs: &'a [(&'a str, Fingerprint)],
+    pub rows: u64,
+    pub transactions_applied: u64,
+    pub diagnostics: &'a [Diagnostic],
+    pub accounts: &'a dyn AccountStore,
+}
+
+pub(crate) fn write_manifest(out: &mut impl Write, manifest: &Manifest) -> io::Result<()> {
+    let mut errors_by_kind = BTreeMap::new();
+    for diagnostic in manifest.diagnostics {
+        if diagnostic.severity == Severity::Error {
+            *errors_by_kind.entry(diagnostic.kind).or_insert(0) += 1;
+        }
+    }
+    let errors = errors_by_kind.values().sum::<u64>();
+    let errors_by_kind = errors_by_kind
+        .iter()
+        .map(|(kind, count)| format!("\"{}\":{}", kind, count))
+        .collect::<Vec<_>>();
+
+    let inputs = manifest
+        .inputs
+        .iter()
+        .map(|(path, fingerprint)| {
+            format!(
+                "{{\"path\":{},\"bytes\":{},\"hash\":\"{:016x}\"}}",
+                json_string(path),
+                fingerprint.bytes,
+                fingerprint.hash
+            )
+        })
+        .collect::<Vec<_>>();
+
+    writeln!(
+        out,
+        "{{\"version\":\"{}\",\"inputs\":[{}],\
+        \"rows\":{},\"transactions_applied\":{},\"errors\":{},\"errors_by_kind\":{{{}}},\"accounts\":{},\"state_hash\":\"{:016x}\"}}",
+        env!("CARGO_PKG_VERSION"),
+        inputs.join(","),
+        manifest.rows,
+        manifest.transactions_applied,
+        errors,
+        errors_by_kind.join(","),
+        manifest.accounts.len(),
+        state_hash(manifest.accounts)
+    )
+}
+
+pub(crate) fn json_string(value: &str) -> String {
+    let mut json = String::from('"');
+    for character in value.chars() {
+        match character {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            character if character.is_control() => {
+                json.push_str(&format!("\\u{:04x}", u32::from(character)))
+            }
+            character => json.push(character),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::ledger::Ledger;
+    use crate::manifest::{state_hash, write_manifest, Fingerprint, Fingerprinting, Manifest};
+    use crate::processing::{process_rows, Processing};
+    use csv::{ReaderBuilder, Trim};
+    use std::collections::HashMap;
+
+    #[test]
+    fn writes_a_manifest_of_the_run() {
+        let input = "type,client,tx,amount
+                        deposit,1,1,10
+                        deposit,2,2,5
+                        withdrawal,1,3,20
+                        withdrawal,2,4,20
+                        dispute,1,9,
+                        deposit,1,1,3";
+        let fingerprinting = Fingerprinting::new(input.as_bytes());
+        let fingerprint = fingerprinting.fingerprint();
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(fingerprinting);
+
+        let mut accounts = HashMap::new();
+        let mut diagnostics = vec![];
+        let mut processing = Processing {
+            diagnostics: Some(&mut diagnostics),
+            ..Default::default()
+        };
+        process_rows(
+            &mut Ledger::default(),
+            &mut accounts,
+            csv.into_deserialize(),
+            &mut processing,
+        );
+        let (rows, applied) = (processing.rows, processing.applied);
+        let manifest = Manifest {
+            inputs: &[("daily \"1\".csv", *fingerprint.borrow())],
+            rows,
+            transactions_applied: applied,
+            diagnostics: &diagnostics,
+            accounts: &accounts,
+        };
+        let mut output = vec![];
+        write_manifest(&mut output, &manifest).unwrap();
+
+        // Known FNV-1a value, which makes sure the hashes stay comparable with external tools
+        let mut known = Fingerprint::default();
+        known.update(b"a");
+        assert_eq!(known.hash, 0xaf63dc4c8601ec8c);
+        assert_eq!(fingerprint.borrow().bytes, input.len() as u64);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!(
+                "{{\"version\":\"{}\",\"inputs\":[{{\"path\":\"daily \\\"1\\\".csv\",\"bytes\":{},\"hash\":\"{:016x}\"}}],\
+                \"rows\":6,\"transactions_applied\":2,\"errors\":4,\"errors_by_kind\":{{\"duplicate_transaction\":1,\"insufficient_funds\":2,\
+                \"non_existent_transaction\":1}},\"accounts\":2,\"state_hash\":\"{:016x}\"}}\n",
+                env!("CARGO_PKG_VERSION"),
+                input.len(),
+                fingerprint.borrow().hash,
+                state_hash(&accounts)
+            )
+        );
+    }
+}
@@ -0,0 +1,192 @@
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::{Decimal, RoundingStrategy};
+
+// Multiplies an amount by a rate (e.g. 0.015 for 1.5%) and rounds the result to the specified scale.
+// Any calculation charging or crediting a percentage of a balance goes through here, so they all
+// round exactly the same way. Nothing is returned when the result doesn't fit in a Decimal
+pub(crate) fn apply_rate(
+    amount: Decimal,
+    rate: Decimal,
+    scale: u32,
+    rounding: RoundingStrategy,
+) -> Option<Decimal> {
+    amount
+        .checked_mul(rate)
+        .map(|applied| applied.round_dp_with_strategy(scale, rounding))
+}
+
+// Scale of every amount in the reports, and of the amounts stored by the ledger
+pub(crate) const REPORTED_SCALE: u32 = 4;
+
+// Rounds an amount of the input to the scale the ledger stores, half to even, so no balance ever
+// holds more decimal places than the reports render
+pub(crate) fn stored(amount: Decimal) -> Decimal {
+    amount.round_dp_with_strategy(REPORTED_SCALE, RoundingStrategy::MidpointNearestEven)
+}
+
+// A double only holds about 15 significant digits, so an amount with more decimal places than that
+// is most likely the noise of a float serialized in full, such as `0.30000000000000004`, rather
+// than a precise amount
+pub(crate) const FLOAT_ARTIFACT_SCALE: u32 = 15;
+
+pub(crate) fn is_float_artifact(amount: Decimal) -> bool {
+    amount.normalize().scale() > FLOAT_ARTIFACT_SCALE
+}
+
+// Rounds an amount the way every report renders it, always with exactly 4 decimal places. Zero has
+// a single representation, so balances which only differ by their history, such as a negative
+// zero, always render as `0.0000`
+pub(crate) fn reported(amount: Decimal) -> Decimal {
+    reported_at(amount, REPORTED_SCALE)
+}
+
+// Same as `reported`, for the columns whose scale was overridden
+pub(crate) fn reported_at(amount: Decimal, scale: u32) -> Decimal {
+    let mut rounded = amount.round_dp(scale);
+    if rounded.is_zero() {
+        rounded = Decimal::ZERO;
+    }
+    rounded.rescale(scale);
+    rounded
+}
+
+// Sum of the amounts, unless it doesn't fit in a Decimal
+pub(crate) fn checked_sum(amounts: impl IntoIterator<Item = Decimal>) -> Option<Decimal> {
+    amounts
+        .into_iter()
+        .try_fold(Decimal::ZERO, |sum, amount| sum.checked_add(amount))
+}
+
+// Rounds every amount to the scale so that the rounded amounts add up to their rounded sum, with
+// the largest remainder method: every amount is rounded down, and the units still missing go to
+// the amounts which lost the most by it, the earliest ones first on ties. Nothing is returned when
+// the amounts add up to more than a Decimal holds
+pub(crate) fn round_reconciled(amounts: &[Decimal], scale: u32) -> Option<Vec<Decimal>> {
+    let unit = Decimal::new(1, scale);
+    let floors = amounts
+        .iter()
+        .map(|amount| amount.round_dp_with_strategy(scale, RoundingStrategy::ToNegativeInfinity))
+        .collect::<Vec<_>>();
+    let target = reported_at(checked_sum(amounts.iter().copied())?, scale);
+    let missing = (target.checked_sub(checked_sum(floors.iter().copied())?)? / unit)
+        .to_usize()
+        .unwrap_or_default();
+
+    let mut by_remainder = (0..amounts.len()).collect::<Vec<_>>();
+    by_remainder.sort_by_key(|index| std::cmp::Reverse(amounts[*index] - floors[*index]));
+    let mut reconciled = floors;
+    for index in by_remainder.into_iter().take(missing) {
+        reconciled[index] = reconciled[index].checked_add(unit)?;
+    }
+    Some(
+        reconciled
+            .into_iter()
+            .map(|amount| reported_at(amount, scale))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::money::{apply_rate, reported, round_reconciled, stored};
+    use rust_decimal::Decimal;
+    use rust_decimal::RoundingStrategy::{
+        AwayFromZero, MidpointAwayFromZero, MidpointNearestEven, MidpointTowardZero,
+        ToNegativeInfinity, ToPositiveInfinity, ToZero,
+    };
+
+    fn decimal(value: &str) -> Decimal {
+        Decimal::from_str_exact(value).unwrap()
+    }
+
+    #[test]
+    fn exact_results_are_identical_under_every_rounding_mode() {
+        for rounding in [
+            MidpointNearestEven,
+            MidpointAwayFromZero,
+            MidpointTowardZero,
+            ToZero,
+            AwayFromZero,
+            ToNegativeInfinity,
+            ToPositiveInfinity,
+        ] {
+            assert_eq!(
+                apply_rate(decimal("100"), decimal("0.015"), 2, rounding),
+                Some(decimal("1.50"))
+            );
+        }
+    }
+
+    #[test]
+    fn rounds_inexact_results_according_to_the_rounding_mode() {
+        let rate =
+            |amount, rounding| apply_rate(decimal(amount), decimal("0.015"), 2, rounding).unwrap();
+
+        // 100.5 * 0.015 = 1.5075 and 101 * 0.015 = 1.515, which sits exactly between two cents
+        assert_eq!(rate("100.5", MidpointNearestEven), decimal("1.51"));
+        assert_eq!(rate("101", MidpointNearestEven), decimal("1.52"));
+        assert_eq!(rate("101", MidpointAwayFromZero), decimal("1.52"));
+        assert_eq!(rate("101", MidpointTowardZero), decimal("1.51"));
+        assert_eq!(rate("100.5", ToZero), decimal("1.50"));
+        assert_eq!(rate("100.5", AwayFromZero), decimal("1.51"));
+        assert_eq!(rate("-100.5", ToNegativeInfinity), decimal("-1.51"));
+        assert_eq!(rate("-100.5", ToPositiveInfinity), decimal("-1.50"));
+    }
+
+    #[test]
+    fn fails_to_apply_rates_overflowing_the_amount() {
+        assert_eq!(
+            apply_rate(Decimal::MAX, decimal("1.5"), 2, MidpointNearestEven),
+            None
+        );
+        assert_eq!(
+            apply_rate(Decimal::MAX, decimal("0.5"), 2, MidpointNearestEven),
+            Some(Decimal::MAX / Decimal::TWO)
+        );
+    }
+
+    #[test]
+    fn reports_every_zero_identically() {
+        for zero in ["0", "0.0000", "-0", "-0.00001", "0.00004"] {
+            let reported = reported(decimal(zero));
+            assert_eq!(reported.to_string(), "0.0000");
+            assert!(!reported.is_sign_negative());
+        }
+    }
+
+    #[test]
+    fn reports_exactly_4_decimal_places() {
+        assert_eq!(reported(decimal("1")).to_string(), "1.0000");
+        assert_eq!(reported(decimal("1.5")).to_string(), "1.5000");
+        assert_eq!(reported(decimal("-2.12345")).to_string(), "-2.1234");
+    }
+
+    #[test]
+    fn stores_amounts_rounded_half_to_even() {
+        // 1.00005 is exactly between 1.0000 and 1.0001, and goes to the even one
+        assert_eq!(stored(decimal("1.00005")).to_string(), "1.0000");
+        assert_eq!(stored(decimal("1.00015")).to_string(), "1.0002");
+        assert_eq!(stored(decimal("1.000051")).to_string(), "1.0001");
+        assert_eq!(stored(decimal("2.5")).to_string(), "2.5");
+    }
+
+    #[test]
+    fn reconciled_amounts_add_up_to_their_rounded_sum() {
+        let amounts = ["0.33333", "0.33333", "0.33333"].map(decimal);
+        let rounded = amounts.map(reported);
+        assert_eq!(rounded.iter().sum::<Decimal>(), decimal("0.9999"));
+        assert_eq!(reported(amounts.iter().sum()), decimal("1"));
+
+        let reconciled = round_reconciled(&amounts, 4).unwrap();
+        assert_eq!(reconciled, ["0.3334", "0.3333", "0.3333"].map(decimal));
+        // Negative amounts are rounded down too, so the largest remainder is still the one closest
+        // to the next unit
+        let reconciled = round_reconciled(&["-0.00004", "0.00009", "-1"].map(decimal), 4);
+        assert_eq!(
+            reconciled.unwrap(),
+            ["-0.0001", "0.0001", "-1"].map(decimal)
+        );
+        // Amounts whose sum overflows can't be reconciled
+        assert_eq!(round_reconciled(&[Decimal::MAX, Decimal::ONE], 4), None);
+    }
+}
@@ -0,0 +1,561 @@
+use crate::account::AccountId;
+use crate::diagnostics::ErrorsFormat;
+use crate::ledger::{HeldRatioAction, InterestDestination, LedgerOptions};
+use crate::report::{Locale, OutputFormat, OutputUnits};
+use crate::store::StoreKind;
+use anyhow::{bail, Error};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+// Path standing for the standard input, which is read when no CSV input is specified
+pub(crate) const STDIN_PATH: &str = "-";
+
+// Every checkpoint rewrites the whole state, whose size grows with the ledger, so checkpointing more
+// often than this would spend more time writing checkpoints than processing rows
+const MIN_CHECKPOINT_INTERVAL: u64 = 1_000;
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) enum InputFormat {
+    #[default]
+    Csv,
+    // One JSON object per line, with the same fields as the CSV columns
+    Jsonl,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct Input {
+    pub path: String,
+    pub format: InputFormat,
+}
+
+// Everything that can be configured from the command line. The only arguments are the paths of the
+// CSV inputs, which default to stdin, every other setting has a default matching the original behavior.
+// Library callers build it with `parse` from the same flags, to render a Report like the command line
+#[derive(Debug, Default)]
+pub struct Options {
+    // Processed in order into the same ledger, either the CSV path arguments or the --input flags
+    pub(crate) inputs: Vec<Input>,
+    pub(crate) format: OutputFormat,
+    // Whether the format was explicitly picked, in which case it's never replaced by the terminal defaults
+    pub(crate) format_forced: bool,
+    // Nests the open disputes of each account in the JSON output
+    pub(crate) json_verbose: bool,
+    // Number formatting of the amounts in the pretty output
+    pub(crate) locale: Option<Locale>,
+    // Decimal places of the report columns whose scale differs from the default one
+    pub(crate) precision: HashMap<String, u32>,
+    // Whether the amounts are written as decimals or as integer minor units of their scale
+    pub(crate) output_units: OutputUnits,
+    // Splits the held funds of every account into the ones held by disputes and by the reserve
+    pub(crate) held_breakdown: bool,
+    // Prints the warnings and errors of every row to stderr, in input order
+    pub(crate) diagnostics: bool,
+    // File receiving the errors of every rejected row, in the specified format
+    pub(crate) errors_file: Option<String>,
+    pub(crate) errors_format: ErrorsFormat,
+    // File receiving a JSON summary of the run, to audit it after the fact
+    pub(crate) manifest: Option<String>,
+    // Prints aggregated statistics about the final accounts to stderr
+    pub(crate) summary: bool,
+    // Prints how far the rounded account totals are from their rounded sum to stderr, along with
+    // the totals which would reconcile them when redistributing
+    pub(crate) rounding_report: bool,
+    pub(crate) redistribute_residual: bool,
+    // Prints the number of rows read and of transactions which changed the state of an account
+    pub(crate) transactions_applied: bool,
+    // Lists the disputes which were never resolved nor charged back to stderr
+    pub(crate) report_dangling_disputes: bool,
+    // Files receiving a copy of the report, which is always written to stdout as well
+    pub(crate) outputs: Vec<String>,
+    // Clients whose balance changes are printed to stderr while the input is processed
+    pub(crate) watched_clients: Vec<AccountId>,
+    // Client whose transaction history and final state are printed to stderr after the report
+    pub(crate) explained_client: Option<AccountId>,
+    // Identifies clients by the text of the client column instead of its numeric value
+    pub(crate) string_client_ids: bool,
+    // File where the processing state is periodically persisted, every `checkpoint_every` rows
+    pub(crate) checkpoint: Option<String>,
+    pub(crate) checkpoint_every: Option<u64>,
+    // Checkpoint to restore before processing, the rows it already covers are skipped
+    pub(crate) resume: Option<String>,
+    // File receiving one row per transaction with its result and the running balance of its account
+    pub(crate) audit_trail: Option<String>,
+    // File receiving one row per dispute opened, resolved or charged back
+    pub(crate) dispute_events: Option<String>,
+    // Threads parsing the input, each one a byte range of it. Only the parsing is parallel, the rows
+    // are still applied one at a time in input order
+    pub(crate) parse_threads: Option<usize>,
+    // Longest line accepted from the input, in bytes
+    pub(crate) max_line_length: Option<usize>,
+    // Wall-clock time the inputs can take to process before the run stops with what it has so far
+    pub(crate) max_processing_time: Option<Duration>,
+    // Prints the input and output schemas instead of processing an input
+    pub(crate) emit_schema: bool,
+    // Only reports the accounts of the 1-based page of the specified size, sorted by client
+    pub(crate) page_size: Option<usize>,
+    pub(crate) page: Option<usize>,
+    // Number of digits the client ids are zero padded to in the report
+    pub(crate) client_width: Option<usize>,
+    // Salt of the tokens replacing the client ids in the report, which are only masked if it's set
+    pub(crate) mask_salt: Option<String>,
+    // Only checks the header and the specified number of rows, without processing them
+    pub(crate) validate_only_schema: Option<usize>,
+    // Map the accounts are kept in while the input is processed
+    pub(crate) accounts_store: StoreKind,
+    pub(crate) ledger: LedgerOptions,
+}
+
+impl Options {
+    pub fn parse(args: &[String]) -> Result<Self, Error> {
+        let mut options = Options::default();
+        let mut paths = vec![];
+        let mut requested_format = None;
+        let mut forbid_withdrawal_create = false;
+        let mut errors_format_requested = false;
+        let mut held_ratio_action_requested = false;
+        let mut held_interest_to_requested = false;
+        let mut mask_clients = false;
+        // Client ids are parsed once every flag is known, since their type depends on --string-client-ids
+        let mut clients = vec![];
+        let mut args = args.iter().skip(1);
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--format" => {
+                    options.format = match value_of(arg, args.next())? {
+                        "csv" => OutputFormat::Csv,
+                        "json" => OutputFormat::Json,
+                        "pretty" => OutputFormat::Pretty,
+                        "kv" => OutputFormat::Kv,
+                        unknown => bail!("{} is not a supported output format", unknown),
+                    };
+                    options.format_forced = true;
+                    requested_format = Some(options.format);
+                }
+                "--json-verbose" => {
+                    options.format = OutputFormat::Json;
+                    options.format_forced = true;
+                    options.json_verbose = true;
+                }
+                "--locale" => {
+                    let locale = value_of(arg, args.next())?;
+                    match Locale::parse(locale) {
+                        Some(locale) => options.locale = Some(locale),
+                        None => bail!("{} is not a supported locale", locale),
+                    }
+                }
+                "--output-units" => {
+                    let units = value_of(arg, args.next())?;
+                    match OutputUnits::parse(units) {
+                        Some(units) => options.output_units = units,
+                        None => bail!("{} is not a supported output unit", units),
+                    }
+                }
+                "--precision" => {
+                    for column_precision in value_of(arg, args.next())?.split(',') {
+                        let (column, scale) = parse_column_precision(column_precision)?;
+                        options.precision.insert(column.to_string(), scale);
+                    }
+                }
+                "--output" => options
+                    .outputs
+                    .push(value_of(arg, args.next())?.to_string()),
+                "--watch-client" => clients.push((arg, value_of(arg, args.next())?)),
+                "--explain-account" => clients.push((arg, value_of(arg, args.next())?)),
+                "--string-client-ids" => options.string_client_ids = true,
+                "--checkpoint" => {
+                    options.checkpoint = Some(value_of(arg, args.next())?.to_string())
+                }
+                "--checkpoint-every" => {
+                    options.checkpoint_every = Some(parse_value(arg, args.next())?)
+                }
+                "--resume" => options.resume = Some(value_of(arg, args.next())?.to_string()),
+                "--audit-trail" => {
+                    options.audit_trail = Some(value_of(arg, args.next())?.to_string())
+                }
+                "--dispute-events" => {
+                    options.dispute_events = Some(value_of(arg, args.next())?.to_string())
+                }
+                "--held-breakdown" => options.held_breakdown = true,
+                "--parse-threads" => options.parse_threads = Some(parse_value(arg, args.next())?),
+                "--diagnostics" => options.diagnostics = true,
+                "--errors-file" => {
+                    options.errors_file = Some(value_of(arg, args.next())?.to_string())
+                }
+                "--errors-format" => {
+                    options.errors_format = match value_of(arg, args.next())? {
+                        "text" => ErrorsFormat::Text,
+                        "csv" => ErrorsFormat::Csv,
+                        unknown => bail!("{} is not a supported errors format", unknown),
+                    };
+                    errors_format_requested = true;
+                }
+                "--max-line-length" => {
+                    options.max_line_length = Some(parse_value(arg, args.next())?)
+                }
+                "--max-processing-time" => {
+                    options.max_processing_time = Some(parse_duration(arg, args.next())?)
+                }
+                "--manifest" => options.manifest = Some(value_of(arg, args.next())?.to_string()),
+                "--emit-schema" => options.emit_schema = true,
+                "--validate-only-schema" => {
+                    options.validate_only_schema = Some(parse_value(arg, args.next())?)
+                }
+                "--page-size" => options.page_size = Some(parse_value(arg, args.next())?),
+                "--page" => options.page = Some(parse_value(arg, args.next())?),
+                "--mask-clients" => mask_clients = true,
+                "--mask-salt" => options.mask_salt = Some(value_of(arg, args.next())?.to_string()),
+                "--client-width" => options.client_width = Some(parse_value(arg, args.next())?),
+                "--summary" => options.summary = true,
+                "--rounding-report" => options.rounding_report = true,
+                "--redistribute-residual" => options.redistribute_residual = true,
+                "--transactions-applied" => options.transactions_applied = true,
+                "--report-dangling-disputes" => options.report_dangling_disputes = true,
+                "--merge-duplicate-deposits" => options.ledger.merge_duplicate_deposits = true,
+                "--max-held" => options.ledger.max_held = Some(parse_value(arg, args.next())?),
+                "--max-held-ratio" => {
+                    options.ledger.max_held_ratio = Some(parse_value(arg, args.next())?)
+                }
+                "--held-ratio-action" => {
+                    options.ledger.held_ratio_action = match value_of(arg, args.next())? {
+                        "warn" => HeldRatioAction::Warn,
+                        "reject" => HeldRatioAction::Reject,
+                        unknown => bail!("{} is not a supported held ratio action", unknown),
+                    };
+                    held_ratio_action_requested = true;
+                }
+                "--max-deposit" => {
+                    options.ledger.max_deposit = Some(parse_value(arg, args.next())?)
+                }
+                "--max-withdrawal" => {
+                    options.ledger.max_withdrawal = Some(parse_value(arg, args.next())?)
+                }
+                "--allow-withdrawal-create" => options.ledger.create_on_withdrawal = true,
+                "--no-create-on-withdrawal" => forbid_withdrawal_create = true,
+                "--no-lock-on-chargeback" => options.ledger.no_lock_on_chargeback = true,
+                "--reject-float-artifacts" => options.ledger.reject_float_artifacts = true,
+                "--dispute-withdrawals" => options.ledger.dispute_withdrawals = true,
+                "--deposit-reserve-pct" => {
+                    options.ledger.deposit_reserve_pct = Some(parse_value(arg, args.next())?)
+                }
+                "--held-interest-pct" => {
+                    options.ledger.held_interest_pct = Some(parse_value(arg, args.next())?)
+                }
+                "--held-interest-to" => {
+                    options.ledger.held_interest_to = match value_of(arg, args.next())? {
+                        "available" => InterestDestination::Available,
+                        "held" => InterestDestination::Held,
+                        unknown => bail!("{} is not a supported interest destination", unknown),
+                    };
+                    held_interest_to_requested = true;
+                }
+                "--cooldown-min-deposit" => {
+                    options.ledger.cooldown_min_deposit = Some(parse_value(arg, args.next())?)
+                }
+                "--cooldown-transactions" => {
+                    options.ledger.cooldown_transactions = Some(parse_value(arg, args.next())?)
+                }
+                "--input" => options
+                    .inputs
+                    .push(parse_input(value_of(arg, args.next())?)?),
+                "--accounts-store" => {
+                    let store = value_of(arg, args.next())?;
+                    match StoreKind::parse(store) {
+                        Some(store) => options.accounts_store = store,
+                        None => bail!("{} is not a supported accounts store", store),
+                    }
+                }
+                flag if flag.starts_with("--") => bail!("Unknown option {}", flag),
+                path => paths.push(path.to_string()),
+            }
+        }
+
+        for (flag, client) in clients {
+            let client = match AccountId::parse(client, options.string_client_ids) {
+                Some(client) => client,
+                None => bail!("{} is not a valid value for {}", client, flag),
+            };
+            match flag.as_str() {
+                "--watch-client" => options.watched_clients.push(client),
+                _ => options.explained_client = Some(client),
+            }
+        }
+
+        if options.emit_schema {
+            if !paths.is_empty() || !options.inputs.is_empty() {
+                bail!("--emit-schema doesn't take a CSV input");
+            }
+        } else if !options.inputs.is_empty() {
+            if !paths.is_empty() {
+                bail!("--input cannot be combined with CSV path arguments");
+            }
+        } else {
+            if paths.is_empty() {
+                paths.push(STDIN_PATH.to_string());
+            }
+            options.inputs = paths
+                .into_iter()
+                .map(|path| Input {
+                    path,
+                    format: InputFormat::Csv,
+                })
+                .collect();
+        }
+        if options.validate_only_schema.is_some()
+            && options
+                .inputs
+                .iter()
+                .any(|input| input.format != InputFormat::Csv)
+        {
+            bail!("--validate-only-schema only checks CSV inputs");
+        }
+        // Checkpoints count the rows of a single input
+        if options.inputs.len() > 1 && (options.checkpoint.is_some() || options.resume.is_some()) {
+            bail!("Checkpoints cannot be combined with several inputs");
+        }
+
+        // --json-verbose implies --format json, so passing both is redundant but an explicit
+        // non JSON format contradicts it
+        if options.json_verbose {
+            match requested_format {
+                Some(OutputFormat::Json) | None => options.format = OutputFormat::Json,
+                Some(format) => bail!(
+                    "--json-verbose cannot be combined with --format {}",
+                    format.name()
+                ),
+            }
+        }
+        if forbid_withdrawal_create && options.ledger.create_on_withdrawal {
+            bail!("--allow-withdrawal-create cannot be combined with --no-create-on-withdrawal");
+        }
+        if let Some(reserve_pct) = options.ledger.deposit_reserve_pct {
+            if reserve_pct.is_sign_negative() || reserve_pct > Decimal::ONE_HUNDRED {
+                bail!("--deposit-reserve-pct must be between 0 and 100");
+            }
+        }
+        if let Some(interest_pct) = options.ledger.held_interest_pct {
+            if interest_pct.is_sign_negative() || interest_pct > Decimal::ONE_HUNDRED {
+                bail!("--held-interest-pct must be between 0 and 100");
+            }
+        }
+        if held_interest_to_requested && options.ledger.held_interest_pct.is_none() {
+            bail!("--held-interest-to requires --held-interest-pct");
+        }
+        match (
+            options.ledger.cooldown_min_deposit,
+            options.ledger.cooldown_transactions,
+        ) {
+            (Some(_), None) => bail!("--cooldown-min-deposit requires --cooldown-transactions"),
+            (None, Some(_)) => bail!("--cooldown-transactions requires --cooldown-min-deposit"),
+            (_, Some(0)) => bail!("--cooldown-transactions must be greater than 0"),
+            _ => {}
+        }
+        // Checkpoints only persist the accounts and the stored transactions
+        if options.ledger.cooldown_min_deposit.is_some()
+            && (options.checkpoint.is_some() || options.resume.is_some())
+        {
+            bail!("--cooldown-min-deposit cannot be combined with checkpoints");
+        }
+        match (mask_clients, &options.mask_salt) {
+            // An unsalted token is just the hash of the id, which anyone can compute for every id
+            (true, None) => bail!("--mask-clients requires --mask-salt"),
+            (false, Some(_)) => bail!("--mask-salt requires --mask-clients"),
+            _ => {}
+        }
+        if options.mask_salt.is_some() && options.client_width.is_some() {
+            bail!("--client-width cannot be combined with --mask-clients");
+        }
+        if options.redistribute_residual && !options.rounding_report {
+            bail!("--redistribute-residual requires --rounding-report");
+        }
+        if errors_format_requested && options.errors_file.is_none() {
+            bail!("--errors-format requires --errors-file");
+        }
+        if held_ratio_action_requested && options.ledger.max_held_ratio.is_none() {
+            bail!("--held-ratio-action requires --max-held-ratio");
+        }
+        if options.string_client_ids && (options.checkpoint.is_some() || options.resume.is_some()) {
+            bail!("--string-client-ids cannot be combined with checkpoints");
+        }
+        match (options.page_size, options.page) {
+            (None, Some(_)) => bail!("--page requires --page-size"),
+            (Some(0), _) => bail!("--page-size must be greater than 0"),
+            (_, Some(0)) => bail!("--page must be greater than 0, pages start at 1"),
+            _ => {}
+        }
+        if options.parse_threads == Some(0) {
+            bail!("--parse-threads must be greater than 0");
+        }
+        if options.parse_threads.is_some() && options.max_line_length.is_some() {
+            bail!("--max-line-length cannot be combined with --parse-threads");
+        }
+        match (options.checkpoint_every, &options.checkpoint) {
+            (Some(_), None) => bail!("--checkpoint-every requires --checkpoint"),
+            (Some(every), Some(_)) if every < MIN_CHECKPOINT_INTERVAL => bail!(
+                "--checkpoint-every must be at least {} rows, every checkpoint rewriting the whole state",
+                MIN_CHECKPOINT_INTERVAL
+            ),
+            _ => {}
+        }
+        Ok(options)
+    }
+
+    // Interactive sessions default to the aligned human readable table, while output piped into
+    // another program keeps the machine friendly CSV. Explicitly selected formats always win
+    pub(crate) fn apply_terminal_defaults(&mut self, stdout_is_terminal: bool) {
+        if !self.format_forced && stdout_is_terminal {
+            self.format = OutputFormat::Pretty;
+        }
+    }
+}
+
+// Parses a `path:format` pair, splitting on the last colon so URLs keep their scheme
+fn parse_input(input: &str) -> Result<Input, Error> {
+    let (path, format) = match input.rsplit_once(':') {
+        Some((path, "csv")) => (path, InputFormat::Csv),
+        Some((path, "jsonl")) => (path, InputFormat::Jsonl),
+        _ => bail!(
+            "{} is not a valid path:format input, the format being csv or jsonl",
+            input
+        ),
+    };
+    Ok(Input {
+        path: path.to_string(),
+        format,
+    })
+}
+
+// Parses a duration such as `500ms`, `30s`, `5m` or `1h`, a bare number being seconds
+fn parse_duration(flag: &str, value: Option<&String>) -> Result<Duration, Error> {
+    let value = value_of(flag, value)?;
+    let split = value
+        .find(|character: char| !character.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let millis = match (amount.parse::<u64>(), unit) {
+        (Ok(amount), "ms") => Some(amount),
+        (Ok(amount), "" | "s") => amount.checked_mul(1_000),
+        (Ok(amount), "m") => amount.checked_mul(60_000),
+        (Ok(amount), "h") => amount.checked_mul(3_600_000),
+        _ => None,
+    };
+    match millis {
+        Some(millis) => Ok(Duration::from_millis(millis)),
+        None => bail!("{} is not a valid duration for {}", value, flag),
+    }
+}
+
+// Parses a `column=scale` pair, the column being one of the amount columns of the report
+fn parse_column_precision(column_precision: &str) -> Result<(&str, u32), Error> {
+    let (column, scale) = match column_precision.split_once('=') {
+        Some(pair) => pair,
+        None => bail!("{} is not a valid column=scale pair", column_precision),
+    };
+    if !PRECISION_COLUMNS.contains(&column) {
+        bail!("{} is not an amount column of the report", column);
+    }
+    match scale.parse() {
+        Ok(scale) if scale <= MAX_SCALE => Ok((column, scale)),
+        _ => bail!("{} is not a valid scale for {}", scale, column),
+    }
+}
+
+const PRECISION_COLUMNS: [&str; 5] = ["available", "held", "dispute_held", "reserve_held", "total"];
+// Largest scale a decimal can represent
+const MAX_SCALE: u32 = 28;
+
+pub(crate) fn parse_value<T: FromStr>(flag: &str, value: Option<&String>) -> Result<T, Error> {
+    let value = value_of(flag, value)?;
+    match value.parse() {
+        Ok(parsed) => Ok(parsed),
+        Err(_) => bail!("{} is not a valid value for {}", value, flag),
+    }
+}
+
+pub(crate) fn value_of<'a>(flag: &str, value: Option<&'a String>) -> Result<&'a str, Error> {
+    match value {
+        Some(value) => Ok(value),
+        None => bail!("{} requires a value", flag),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::options::Options;
+    use crate::report::OutputFormat;
+    use std::time::Duration;
+
+    #[test]
+    fn rejects_conflicting_options() {
+        let parse = |args: &[&str]| {
+            let args = std::iter::once("transaction_processor")
+                .chain(args.iter().copied())
+                .chain(std::iter::once("tests/basic.csv"))
+                .map(String::from)
+                .collect::<Vec<_>>();
+            Options::parse(&args).map_err(|e| e.to_string())
+        };
+
+        assert_eq!(
+            parse(&["--format", "csv", "--json-verbose"]).unwrap_err(),
+            "--json-verbose cannot be combined with --format csv"
+        );
+        assert_eq!(
+            parse(&["--json-verbose", "--format", "pretty"]).unwrap_err(),
+            "--json-verbose cannot be combined with --format pretty"
+        );
+        assert_eq!(
+            parse(&["--checkpoint-every", "10"]).unwrap_err(),
+            "--checkpoint-every requires --checkpoint"
+        );
+        assert_eq!(
+            parse(&["--checkpoint", "state.csv", "--checkpoint-every", "0"]).unwrap_err(),
+            "--checkpoint-every must be at least 1000 rows, every checkpoint rewriting the whole state"
+        );
+        assert_eq!(
+            parse(&["--checkpoint", "state.csv", "--checkpoint-every", "999"]).unwrap_err(),
+            "--checkpoint-every must be at least 1000 rows, every checkpoint rewriting the whole state"
+        );
+        assert!(parse(&["--checkpoint", "state.csv", "--checkpoint-every", "1000"]).is_ok());
+        assert_eq!(
+            parse(&["--mask-clients"]).unwrap_err(),
+            "--mask-clients requires --mask-salt"
+        );
+        assert_eq!(
+            parse(&["--held-interest-pct", "150"]).unwrap_err(),
+            "--held-interest-pct must be between 0 and 100"
+        );
+        assert_eq!(
+            parse(&["--held-interest-pct", "-1"]).unwrap_err(),
+            "--held-interest-pct must be between 0 and 100"
+        );
+
+        // Redundant combinations are accepted
+        let options = parse(&["--format", "json", "--json-verbose"]).unwrap();
+        assert_eq!(options.format, OutputFormat::Json);
+        assert!(options.json_verbose);
+    }
+
+    #[test]
+    fn parses_the_processing_time_budget() {
+        let parse = |duration: &str| {
+            let args = ["transaction_processor", "--max-processing-time", duration]
+                .map(String::from)
+                .to_vec();
+            Options::parse(&args)
+                .map(|options| options.max_processing_time)
+                .map_err(|e| e.to_string())
+        };
+
+        assert_eq!(parse("500ms").unwrap(), Some(Duration::from_millis(500)));
+        assert_eq!(parse("2m").unwrap(), Some(Duration::from_secs(120)));
+        assert_eq!(parse("30").unwrap(), Some(Duration::from_secs(30)));
+        assert_eq!(
+            parse("1.5s").unwrap_err(),
+            "1.5s is not a valid duration for --max-processing-time"
+        );
+    }
+}
@@ -0,0 +1,243 @@
+use crate::account::{Account, AccountId};
+use crate::processing::{process_rows, Processing, Row};
+use crate::transaction::TransactionType::{Deposit, Withdrawal};
+use crate::transaction::{Transaction, TransactionId};
+use crate::Ledger;
+use anyhow::Error;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::mpsc;
+use std::{iter, thread};
+
+// Rows a shard can fall behind the reader before the reader waits for it
+const QUEUE_SIZE: usize = 1024;
+
+enum Message {
+    Row(Transaction),
+    // Whether the shard applied a deposit or withdrawal with the id, answered once every row
+    // queued before it has been applied
+    Applied(TransactionId, mpsc::Sender<bool>),
+}
+
+// Applies the rows on several threads, each one owning the accounts of a shard of the clients.
+//
+// Every transaction only changes the account of its own client, so the rows are read once and
+// every row is queued into the shard of its client, which keeps the order of the rows of each
+// client. Every shard applies its rows with its own ledger and the accounts of the shards are then
+// merged, which gives the same balances as applying every row in order.
+//
+// Transaction ids are unique across clients though. When a row reuses an id which was also used by
+// a deposit or withdrawal queued into another shard, the reader waits for that shard to tell
+// whether it applied it, and if it did the row is queued there instead: a reused deposit or
+// withdrawal id is rejected as a duplicate before any account is touched, and disputes, resolves
+// and chargebacks find the transaction they reference. Rejected deposits and withdrawals never
+// claim their id, just like when applied in order.
+//
+// The errors are listed shard by shard instead of in input order, after the rows which couldn't be
+// parsed, and rows referencing the transaction of another client may fail with a different error.
+// Batches spanning several clients are rolled back per shard, so they're only atomic for their
+// rows in the same shard, and a batch is also ended by a query about one of its ids.
+pub(crate) fn process_sharded(
+    rows: impl IntoIterator<Item = impl Row>,
+    shards: usize,
+) -> (HashMap<AccountId, Account>, Vec<Error>) {
+    let shards = shards.max(1);
+    let mut errors = vec![];
+    // Shards which were sent a deposit or withdrawal with every id
+    let mut claims = HashMap::<TransactionId, Vec<usize>>::new();
+
+    thread::scope(|scope| {
+        let (queues, workers): (Vec<_>, Vec<_>) = (0..shards)
+            .map(|_| {
+                let (queue, messages) = mpsc::sync_channel(QUEUE_SIZE);
+                let worker = scope.spawn(move || apply_shard(messages));
+                (queue, worker)
+            })
+            .unzip();
+        let applied = |shard: usize, transaction_id| {
+            let (reply, answer) = mpsc::channel();
+            queues[shard]
+                .send(Message::Applied(transaction_id, reply))
+                .expect("shard worker stopped");
+            answer.recv().expect("shard worker stopped")
+        };
+
+        for row in rows {
+            let transaction: Transaction = match row.into_result() {
+                Ok(transaction) => transaction,
+                Err(e) => {
+                    errors.push(e);
+                    continue;
+                }
+            };
+            let own = shard(&transaction.account_id, shards);
+            let id = transaction.transaction_id;
+            // At most one shard holds an applied transaction with the id
+            let owner = claims
+                .get(&id)
+                .into_iter()
+                .flatten()
+                .copied()
+                .filter(|&claimant| claimant != own)
+                .find(|&claimant| applied(claimant, id));
+            if let (None, Deposit(..) | Withdrawal(..)) = (owner, transaction.transaction_type) {
+                let claimants = claims.entry(id).or_default();
+                if !claimants.contains(&own) {
+                    claimants.push(own);
+                }
+            }
+            queues[owner.unwrap_or(own)]
+                .send(Message::Row(transaction))
+                .expect("shard worker stopped");
+        }
+        drop(queues);
+
+        // Rows of other clients never open an account, so every client comes from a single shard
+        let mut accounts = HashMap::new();
+        for worker in workers {
+            let (shard_accounts, shard_errors) = worker.join().expect("shard worker panicked");
+            for (account_id, account) in shard_accounts {
+                let merged = accounts.insert(account_id, account);
+                assert!(
+                    merged.is_none(),
+                    "{} was applied by several shards",
+                    account_id
+                );
+            }
+            errors.extend(shard_errors);
+        }
+        (accounts, errors)
+    })
+}
+
+// Applies the rows queued into a shard, stopping in between to answer the queries of the reader
+fn apply_shard(messages: mpsc::Receiver<Message>) -> (HashMap<AccountId, Account>, Vec<Error>) {
+    let mut ledger = Ledger::default();
+    let mut accounts = HashMap::new();
+    let mut errors = vec![];
+    let mut processing = Processing::default();
+    loop {
+        let mut query = None;
+        let rows = iter::from_fn(|| match messages.recv().ok()? {
+            Message::Row(transaction) => Some(Ok::<_, Error>(transaction)),
+            Message::Applied(transaction_id, reply) => {
+                query = Some((transaction_id, reply));
+                None
+            }
+        });
+        errors.extend(process_rows(
+            &mut ledger,
+            &mut accounts,
+            rows,
+            &mut processing,
+        ));
+        match query {
+            // The reader may have stopped waiting if it panicked
+            Some((transaction_id, reply)) => {
+                let _ = reply.send(ledger.amount(transaction_id).is_some());
+            }
+            None => return (accounts, errors),
+        }
+    }
+}
+
+fn shard(client: &AccountId, shards: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    client.hash(&mut hasher);
+    (hasher.finish() % shards as u64) as usize
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::parallel::process_sharded;
+    use crate::processing::tests::process_csv;
+    use crate::{transactions, AccountId};
+    use csv::{ReaderBuilder, Trim};
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn applies_clients_in_parallel_like_in_order() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,10
+                        deposit,2,2,5
+                        deposit,3,3,7.5
+                        withdrawal,2,4,6
+                        dispute,1,1,
+                        deposit,4,5,3
+                        deposit,2,1,4
+                        withdrawal,1,6,1
+                        chargeback,1,1,
+                        deposit,1,7,2
+                        dispute,3,3,
+                        withdrawal,4,8,1.25
+                        resolve,3,3,
+                        dispute,2,5,
+                        withdrawal,2,9,2
+                        deposit,5,10,bad";
+        let reader = || {
+            ReaderBuilder::new()
+                .has_headers(true)
+                .trim(Trim::All)
+                .flexible(true)
+                .from_reader(csv.as_bytes())
+        };
+
+        let (accounts, errors) = process_csv(reader());
+        let (parallel_accounts, parallel_errors) =
+            process_sharded(transactions(reader(), false), 4);
+
+        assert_eq!(parallel_accounts, accounts);
+        assert_eq!(parallel_errors.len(), errors.len());
+        // The deposit reusing the id of client 1 is rejected as a duplicate in both cases
+        assert!(parallel_errors
+            .iter()
+            .any(|error| error.to_string() == "TransactionId(1) already exists"));
+    }
+
+    #[test]
+    fn applies_reused_ids_in_parallel_like_in_order() {
+        // The failed withdrawal of client 1 doesn't claim id 5, so the deposit of client 2 is
+        // applied and later disputed, while the deposit of client 3 reusing it is a duplicate
+        let csv = "type,client,tx,amount
+                        deposit,1,1,1
+                        withdrawal,1,5,100
+                        deposit,2,2,9
+                        deposit,2,5,1
+                        deposit,3,5,4
+                        deposit,3,3,2
+                        dispute,2,5,
+                        dispute,3,5,
+                        deposit,4,4,3
+                        withdrawal,4,1,1
+                        withdrawal,4,6,1
+                        dispute,1,6,
+                        deposit,1,6,2
+                        dispute,2,5,";
+        let reader = || {
+            ReaderBuilder::new()
+                .has_headers(true)
+                .trim(Trim::All)
+                .flexible(true)
+                .from_reader(csv.as_bytes())
+        };
+
+        let (accounts, errors) = process_csv(reader());
+        assert_eq!(
+            accounts[&AccountId::Number(2)].available(),
+            Decimal::from(9)
+        );
+        assert_eq!(accounts[&AccountId::Number(2)].held(), Decimal::from(1));
+        for shards in 1..=8 {
+            let (parallel_accounts, parallel_errors) =
+                process_sharded(transactions(reader(), false), shards);
+
+            assert_eq!(parallel_accounts, accounts, "{} shards", shards);
+            assert_eq!(parallel_errors.len(), errors.len(), "{} shards", shards);
+            assert!(!parallel_errors
+                .iter()
+                .any(|error| error.to_string() == "TransactionId(5) not found"));
+        }
+    }
+}
@@ -0,0 +1,780 @@
+use crate::diagnostics::{self, Diagnostic};
+use crate::report::AuditResult;
+use crate::transaction::TransactionFailure::{BatchRolledBack, SkippedBatchTransaction};
+use crate::transaction::TransactionType::{Chargeback, Dispute, Resolve};
+use crate::transaction::{BatchId, Outcome};
+use crate::{checkpoint, report, Account, AccountStore, Ledger, Transaction};
+use anyhow::Error;
+use rust_decimal::Decimal;
+use std::io::Write;
+use std::path::Path;
+use std::time::Instant;
+
+// Rows processed between two checks of the --max-processing-time deadline
+const DEADLINE_CHECK_INTERVAL: u64 = 100;
+
+// A parsed row along with the line it was read from
+pub(crate) struct LocatedRow {
+    pub line: u64,
+    pub row: Result<Transaction, Error>,
+}
+
+// Rows traversed by `process_rows`, which only know their line if their reader tracked it
+pub(crate) trait Row {
+    fn line(&self) -> Option<u64>;
+    fn into_result(self) -> Result<Transaction, Error>;
+}
+
+impl Row for LocatedRow {
+    fn line(&self) -> Option<u64> {
+        Some(self.line)
+    }
+
+    fn into_result(self) -> Result<Transaction, Error> {
+        self.row
+    }
+}
+
+impl<E: Into<Error>> Row for Result<Transaction, E> {
+    fn line(&self) -> Option<u64> {
+        None
+    }
+
+    fn into_result(self) -> Result<Transaction, Error> {
+        self.map_err(Into::into)
+    }
+}
+
+// Batch the last rows belonged to, which once failed skips the rest of its rows
+#[derive(Copy, Clone)]
+struct OpenBatch {
+    id: BatchId,
+    failed: bool,
+}
+
+// Where the processing state gets persisted and after how many rows
+pub(crate) struct Checkpointing<'a> {
+    pub path: &'a Path,
+    pub every: u64,
+}
+
+// Optional behaviors of a traversal over the input rows
+#[derive(Default)]
+pub(crate) struct Processing<'a> {
+    // Rows already covered by a resumed checkpoint, which are skipped
+    pub processed_rows: u64,
+    // Rows traversed so far, including the skipped ones
+    pub rows: u64,
+    // Path of the input, prefixed to the diagnostic messages when several inputs are processed
+    pub source: Option<&'a str>,
+    // Transactions which changed the state of the ledger and were not rolled back
+    pub applied: u64,
+    pub checkpointing: Option<Checkpointing<'a>>,
+    // Receives one row per transaction with its result and the resulting balance of its account
+    pub audit_trail: Option<&'a mut dyn Write>,
+    // Audit rows of the open batch along with the account they left, only written once the batch
+    // commits or, as rolled back, once it fails
+    pub pending_audit: Vec<(Transaction, AuditResult, Option<Account>)>,
+    // Receives one row per applied dispute, resolve and chargeback, numbered from 1
+    pub dispute_events: Option<&'a mut dyn Write>,
+    pub dispute_events_logged: u64,
+    // Dispute events of the open batch, only logged once it commits and dropped if it fails
+    pub pending_dispute_events: Vec<(Transaction, Decimal)>,
+    // Collects the warnings and errors of every row in input order, tagged with their line
+    pub diagnostics: Option<&'a mut Vec<Diagnostic>>,
+    // Time by which the traversal stops, leaving the rest of the rows unread
+    pub deadline: Option<Instant>,
+    pub budget_exceeded: bool,
+}
+
+// Traverses the specified parsed rows, modifying the accounts according to all valid transactions
+// Returns an array containing all the errors (parsing and logical) found during the traversal
+pub(crate) fn process_rows(
+    ledger: &mut Ledger,
+    accounts: &mut (impl AccountStore + ?Sized),
+    rows: impl IntoIterator<Item = impl Row>,
+    processing: &mut Processing,
+) -> Vec<Error> {
+    let mut errors: Vec<Error> = vec![];
+    let mut batch: Option<OpenBatch> = None;
+    let mut last_line = 1;
+
+    for (row_index, row) in (1..).zip(rows) {
+        processing.rows = row_index;
+        if row_index <= processing.processed_rows {
+            continue;
+        }
+        // Rows whose line is unknown are assumed to follow each other right after the header
+        let line = row.line().unwrap_or(row_index + 1);
+        last_line = line;
+        // Collected once the row is done, since applying it can touch any part of the processing
+        let mut row_diagnostics = vec![];
+        let mut diagnose = |diagnostic| row_diagnostics.push(diagnostic);
+        let mut parsed = None;
+        let mut rolled_back = None;
+        let mut process_row = |row: Result<Transaction, Error>| {
+            let transaction = row?;
+            parsed = Some(transaction);
+            if let Some(warning) = diagnostics::precision_warning(line, &transaction) {
+                diagnose(warning);
+            }
+            // A batch ends with the first row which doesn't share its id
+            if transaction.batch != batch.map(|open| open.id) {
+                commit_batch(ledger, processing)?;
+                batch = transaction.batch.map(|id| {
+                    ledger.begin_batch();
+                    OpenBatch { id, failed: false }
+                });
+            }
+            let result = match batch {
+                Some(OpenBatch { id, failed: true }) => {
+                    Err(SkippedBatchTransaction(transaction.transaction_id, id))
+                }
+                _ => ledger.process_transaction(accounts, transaction),
+            };
+            if let Ok(Outcome::Applied) = result {
+                processing.applied += 1;
+            }
+            if let (Err(_), Some(open)) = (&result, &mut batch) {
+                if !open.failed {
+                    open.failed = true;
+                    let reverted = ledger.rollback_batch(accounts);
+                    processing.applied -= reverted as u64;
+                    rolled_back = Some(BatchRolledBack(open.id, reverted));
+                    processing.pending_dispute_events.clear();
+                    if let Some(audit_trail) = &mut processing.audit_trail {
+                        for (transaction, _, _) in processing.pending_audit.drain(..) {
+                            report::write_audit_row(
+                                audit_trail,
+                                &transaction,
+                                AuditResult::RolledBack,
+                                accounts.account(&transaction.account_id),
+                            )?;
+                        }
+                    }
+                }
+            }
+            if let (Ok(Outcome::Applied), Some(account)) =
+                (&result, accounts.account(&transaction.account_id))
+            {
+                if let Some(warning) = ledger.warning(&transaction, account) {
+                    diagnose(Diagnostic::warning(
+                        line,
+                        &transaction,
+                        "held_ratio",
+                        warning,
+                    ));
+                }
+            }
+            if let Some(audit_trail) = &mut processing.audit_trail {
+                let account = accounts.account(&transaction.account_id);
+                match batch {
+                    Some(OpenBatch { failed: false, .. }) => processing.pending_audit.push((
+                        transaction,
+                        AuditResult::of(&result),
+                        account.cloned(),
+                    )),
+                    _ => report::write_audit_row(
+                        audit_trail,
+                        &transaction,
+                        AuditResult::of(&result),
+                        account,
+                    )?,
+                }
+            }
+            if let (Ok(Outcome::Applied), Some(dispute_events)) =
+                (&result, &mut processing.dispute_events)
+            {
+                if let (Dispute | Resolve | Chargeback, Some(amount)) = (
+                    transaction.transaction_type,
+                    ledger.amount(transaction.transaction_id),
+                ) {
+                    if batch.is_some() {
+                        processing
+                            .pending_dispute_events
+                            .push((transaction, amount));
+                    } else {
+                        processing.dispute_events_logged += 1;
+                        report::write_dispute_event(
+                            dispute_events,
+                            processing.dispute_events_logged,
+                            &transaction,
+                            amount,
+                        )?;
+                    }
+                }
+            }
+            result?;
+            Ok::<_, Error>(())
+        };
+        if let Err(e) = process_row(row.into_result()) {
+            diagnose(Diagnostic::error(line, parsed.as_ref(), &e));
+            errors.push(e);
+        }
+        if let Some(rolled_back) = rolled_back {
+            let e = Error::from(rolled_back);
+            diagnose(Diagnostic::error(line, parsed.as_ref(), &e));
+            errors.push(e);
+        }
+
+        // Checkpoints never capture half a batch, whose first rows would be skipped when resuming
+        if let Some(Checkpointing { path, every }) = &processing.checkpointing {
+            if row_index % every == 0 && batch.is_none() {
+                if let Err(e) = checkpoint::write_checkpoint(path, row_index, ledger, accounts) {
+                    diagnose(Diagnostic::error(line, None, &e));
+                    errors.push(e);
+                }
+            }
+        }
+        for diagnostic in row_diagnostics {
+            collect_diagnostic(&mut processing.diagnostics, processing.source, diagnostic);
+        }
+        // Like checkpoints, the traversal never stops in the middle of a batch
+        if let Some(deadline) = processing.deadline {
+            if row_index % DEADLINE_CHECK_INTERVAL == 0
+                && batch.is_none()
+                && Instant::now() >= deadline
+            {
+                processing.budget_exceeded = true;
+                break;
+            }
+        }
+    }
+    if let Err(e) = commit_batch(ledger, processing) {
+        let diagnostic = Diagnostic::error(last_line, None, &e);
+        collect_diagnostic(&mut processing.diagnostics, processing.source, diagnostic);
+        errors.push(e);
+    }
+    errors
+}
+
+fn collect_diagnostic(
+    diagnostics: &mut Option<&mut Vec<Diagnostic>>,
+    source: Option<&str>,
+    mut diagnostic: Diagnostic,
+) {
+    if let Some(diagnostics) = diagnostics {
+        if let Some(source) = source {
+            diagnostic.message = format!("{}: {}", source, diagnostic.message);
+        }
+        diagnostics.push(diagnostic);
+    }
+}
+
+// Commits the open batch of the ledger along with the audit rows and dispute events it left pending
+fn commit_batch(ledger: &mut Ledger, processing: &mut Processing) -> Result<(), Error> {
+    ledger.commit_batch();
+    if let Some(audit_trail) = &mut processing.audit_trail {
+        for (transaction, result, account) in processing.pending_audit.drain(..) {
+            report::write_audit_row(audit_trail, &transaction, result, account.as_ref())?;
+        }
+    }
+    if let Some(dispute_events) = &mut processing.dispute_events {
+        for (transaction, amount) in processing.pending_dispute_events.drain(..) {
+            processing.dispute_events_logged += 1;
+            report::write_dispute_event(
+                dispute_events,
+                processing.dispute_events_logged,
+                &transaction,
+                amount,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+
+    use crate::ledger::{Ledger, LedgerOptions};
+    use crate::processing::{process_rows, Processing};
+    use crate::{input_reader, transactions, Account, AccountId};
+    use anyhow::Error;
+    use csv::{Reader, ReaderBuilder, Trim};
+    use rust_decimal::Decimal;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::io::Read;
+    use std::rc::Rc;
+    use std::time::{Duration, Instant};
+
+    pub(crate) fn process_csv(csv: Reader<impl Read>) -> (HashMap<AccountId, Account>, Vec<Error>) {
+        process_csv_with_ledger(&mut Ledger::default(), csv)
+    }
+
+    pub(crate) fn process_csv_with_ledger(
+        ledger: &mut Ledger,
+        csv: Reader<impl Read>,
+    ) -> (HashMap<AccountId, Account>, Vec<Error>) {
+        let mut accounts = HashMap::new();
+        let errors = process_rows(
+            ledger,
+            &mut accounts,
+            transactions(csv, false),
+            &mut Processing::default(),
+        );
+        (accounts, errors)
+    }
+
+    #[test]
+    fn processes_regular_transactions_correctly() {
+        let csv = "type,client,tx,amount
+                        deposit, 1, 1, 1
+                        deposit, 1, 2, 1
+                        withdrawal, 1, 3, 0.5";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let (accounts, errors) = process_csv(csv);
+        let first_account = accounts.get(&AccountId::Number(1)).unwrap();
+        assert_eq!(
+            first_account.available(),
+            Decimal::from_str_exact("1.5").unwrap()
+        );
+        assert_eq!(errors.len(), 0);
+    }
+
+    #[test]
+    fn processes_dispute_correctly() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,1.0001
+                        dispute, 1, 1";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let (accounts, errors) = process_csv(csv);
+        let first_account = accounts.get(&AccountId::Number(1)).unwrap();
+        assert_eq!(
+            first_account.available(),
+            Decimal::from_str_exact("0").unwrap()
+        );
+        assert_eq!(
+            first_account.held(),
+            Decimal::from_str_exact("1.0001").unwrap()
+        );
+        assert_eq!(first_account.held(), first_account.total());
+        assert_eq!(errors.len(), 0);
+    }
+
+    #[test]
+    fn processes_resolve_correctly() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,1.0001
+                        dispute, 1, 1,
+                        resolve, 1, 1";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let (accounts, errors) = process_csv(csv);
+        let first_account = accounts.get(&AccountId::Number(1)).unwrap();
+        assert_eq!(
+            first_account.available(),
+            Decimal::from_str_exact("1.0001").unwrap()
+        );
+        assert_eq!(first_account.held(), Decimal::from_str_exact("0").unwrap());
+        assert_eq!(first_account.available(), first_account.total());
+        assert_eq!(errors.len(), 0);
+    }
+
+    #[test]
+    fn processes_chargeback_correctly() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,1.0001
+                        dispute, 1, 1,
+                        chargeback, 1, 1";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let (accounts, errors) = process_csv(csv);
+        let first_account = accounts.get(&AccountId::Number(1)).unwrap();
+        assert_eq!(
+            first_account.available(),
+            Decimal::from_str_exact("0").unwrap()
+        );
+        assert_eq!(first_account.held(), Decimal::from_str_exact("0").unwrap());
+        assert!(first_account.locked());
+        assert_eq!(errors.len(), 0);
+    }
+
+    #[test]
+    fn parses_csv_with_logic_errors_correctly() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,1.0001
+                        deposit,2,2,2.1000
+                        deposit,1,3,2.0
+                        withdrawal,1,4,1.5
+                        withdrawal,2,5,3.0,
+                        withdrawal,2,6,1.1,
+                        withdrawal,3,7,1.1,
+                        dispute,2,5,
+                        dispute,2,6,
+                        dispute,1,1,
+                        resolve,1,1,
+                        chargeback,1,1,
+                        dispute,2,2";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let (accounts, errors) = process_csv(csv);
+        let (first_account, second_account) = (
+            accounts.get(&AccountId::Number(1)).unwrap(),
+            accounts.get(&AccountId::Number(2)).unwrap(),
+        );
+        assert_eq!(
+            first_account.available(),
+            Decimal::from_str_exact("1.5001").unwrap()
+        );
+        assert_eq!(
+            second_account.held(),
+            Decimal::from_str_exact("2.1").unwrap()
+        );
+        assert_eq!(
+            second_account.available(),
+            // At this point the user should have a negative available balance because they withdrew
+            // some funds from the account and then reverted the original funding deposit
+            Decimal::from_str_exact("-1.1").unwrap()
+        );
+        assert_eq!(errors.len(), 5);
+        assert_eq!(
+            errors[0].to_string(),
+            "TransactionId(5) for AccountId(2) can't withdraw $3 due to insufficient funds"
+        );
+        assert_eq!(errors[1].to_string(), "AccountId(3) not found");
+        assert_eq!(errors[2].to_string(), "TransactionId(5) not found");
+        assert_eq!(
+            errors[3].to_string(),
+            "Transaction #6 is a withdrawal and cannot be disputed"
+        );
+        assert_eq!(errors[4].to_string(), "Transaction #1 is not under dispute");
+    }
+
+    #[test]
+    fn audit_trail_tracks_running_balances() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,10
+                        withdrawal,1,2,3
+                        deposit,1,1,10
+                        withdrawal,1,3,20
+                        deposit,1,4,2.5
+                        dispute,1,1,
+                        dispute,2,1,";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let mut audit_trail = vec![];
+        let mut accounts = HashMap::new();
+        let errors = process_rows(
+            &mut Ledger::with_options(LedgerOptions {
+                merge_duplicate_deposits: true,
+                ..Default::default()
+            }),
+            &mut accounts,
+            csv.into_deserialize(),
+            &mut Processing {
+                audit_trail: Some(&mut audit_trail),
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(errors.len(), 2);
+        // Rejected transactions keep the previous balance, or zero when the account doesn't exist,
+        // and the redelivered deposit is told apart from the ones which moved funds
+        assert_eq!(
+            String::from_utf8(audit_trail).unwrap(),
+            "deposit,1,1,applied,10.0000,0.0000
+withdrawal,1,2,applied,7.0000,0.0000
+deposit,1,1,ignored,7.0000,0.0000
+withdrawal,1,3,rejected,7.0000,0.0000
+deposit,1,4,applied,9.5000,0.0000
+dispute,1,1,applied,-0.5000,10.0000
+dispute,2,1,rejected,0.0000,0.0000
+"
+        );
+    }
+
+    #[test]
+    fn rolls_back_every_row_of_a_failed_batch() {
+        let csv = "type,client,tx,amount,batch
+                        deposit,1,1,10,
+                        deposit,1,2,20,
+                        deposit,1,3,30,
+                        dispute,1,1,,7
+                        dispute,1,4,,7
+                        dispute,1,2,,7
+                        dispute,1,3,,8";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let (accounts, errors) = process_csv(csv);
+        let first_account = accounts.get(&AccountId::Number(1)).unwrap();
+
+        // The missing transaction reverts the dispute before it and skips the one after it, so only
+        // the next batch holds funds
+        assert_eq!(first_account.held(), Decimal::from(30));
+        assert_eq!(first_account.available(), Decimal::from(30));
+        assert_eq!(first_account.total(), Decimal::from(60));
+        let errors = errors
+            .iter()
+            .map(|error| error.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            [
+                "TransactionId(4) not found",
+                "Batch #7 was rolled back after applying 1 of its transactions",
+                "Transaction #2 was skipped because batch #7 was rolled back",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_the_rows_of_a_failed_batch_as_rolled_back() {
+        let csv = "type,client,tx,amount,batch
+                        deposit,1,1,10,
+                        deposit,1,2,20,1
+                        dispute,1,1,,1
+                        dispute,1,99,,1
+                        deposit,1,3,5,2
+                        deposit,1,4,5,2
+                        deposit,1,5,1,";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let mut ledger = Ledger::default();
+        let notified = Rc::new(RefCell::new(vec![]));
+        let recorded = notified.clone();
+        ledger.on_account_change(AccountId::Number(1), move |transaction, account| {
+            recorded
+                .borrow_mut()
+                .push((transaction.transaction_id.0, account.available()))
+        });
+        let mut audit_trail = vec![];
+        process_rows(
+            &mut ledger,
+            &mut HashMap::new(),
+            csv.into_deserialize(),
+            &mut Processing {
+                audit_trail: Some(&mut audit_trail),
+                ..Default::default()
+            },
+        );
+
+        // The reverted rows show the balances they were reverted to, and their listeners never fire
+        assert_eq!(
+            String::from_utf8(audit_trail).unwrap(),
+            "deposit,1,1,applied,10.0000,0.0000
+deposit,1,2,rolled_back,10.0000,0.0000
+dispute,1,1,rolled_back,10.0000,0.0000
+dispute,1,99,rejected,10.0000,0.0000
+deposit,1,3,applied,15.0000,0.0000
+deposit,1,4,applied,20.0000,0.0000
+deposit,1,5,applied,21.0000,0.0000
+"
+        );
+        assert_eq!(
+            *notified.borrow(),
+            [
+                (1, Decimal::from(10)),
+                (3, Decimal::from(15)),
+                (4, Decimal::from(20)),
+                (5, Decimal::from(21))
+            ]
+        );
+    }
+
+    #[test]
+    fn counts_applied_transactions_apart_from_rows_read() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,10
+                        withdrawal,1,2,20
+                        deposit,2,3,5
+                        dispute,2,3,
+                        dispute,2,3,
+                        resolve,1,9,
+                        withdrawal,2,4,1";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let mut accounts = HashMap::new();
+        let mut processing = Processing::default();
+        let errors = process_rows(
+            &mut Ledger::default(),
+            &mut accounts,
+            transactions(csv, false),
+            &mut processing,
+        );
+
+        assert_eq!(errors.len(), 4);
+        assert_eq!(processing.rows, 7);
+        assert_eq!(processing.applied, 3);
+        assert_eq!(processing.rows - processing.applied, errors.len() as u64);
+    }
+
+    #[test]
+    fn withdrawals_respect_the_funds_held_by_earlier_disputes() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,10
+                        deposit,1,2,5
+                        dispute,1,1,
+                        withdrawal,1,3,8
+                        withdrawal,1,4,5
+                        resolve,1,1,
+                        withdrawal,1,5,8";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let (accounts, errors) = process_csv(csv);
+        let first_account = accounts.get(&AccountId::Number(1)).unwrap();
+
+        // Only 5 are available while the first deposit is disputed, and its funds can be withdrawn
+        // again once the dispute is resolved
+        assert_eq!(first_account.available(), Decimal::TWO);
+        assert_eq!(first_account.held(), Decimal::ZERO);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "TransactionId(3) for AccountId(1) can't withdraw $8 due to insufficient funds"
+        );
+    }
+
+    #[test]
+    fn logs_the_dispute_lifecycle() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,10
+                        deposit,1,2,5
+                        dispute,1,2,
+                        resolve,1,1,
+                        chargeback,1,2,";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let mut dispute_events = vec![];
+        let errors = process_rows(
+            &mut Ledger::default(),
+            &mut HashMap::new(),
+            csv.into_deserialize(),
+            &mut Processing {
+                dispute_events: Some(&mut dispute_events),
+                ..Default::default()
+            },
+        );
+
+        // The rejected resolve doesn't log an event nor take a sequence number
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            String::from_utf8(dispute_events).unwrap(),
+            "1,opened,1,2,5.0000
+2,charged_back,1,2,5.0000
+"
+        );
+    }
+
+    #[test]
+    fn only_logs_the_dispute_events_of_committed_batches() {
+        let csv = "type,client,tx,amount,batch
+                        deposit,1,1,10,
+                        deposit,1,2,10,
+                        dispute,1,1,,1
+                        dispute,1,2,,1
+                        dispute,1,99,,1
+                        dispute,1,1,,2
+                        resolve,1,1,,2
+                        dispute,1,2,,";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let mut dispute_events = vec![];
+        let mut accounts = HashMap::new();
+        let errors = process_rows(
+            &mut Ledger::default(),
+            &mut accounts,
+            csv.into_deserialize(),
+            &mut Processing {
+                dispute_events: Some(&mut dispute_events),
+                ..Default::default()
+            },
+        );
+
+        // The disputes of the rolled back batch never took effect, so they're never logged
+        assert_eq!(errors.len(), 2);
+        assert_eq!(accounts[&AccountId::Number(1)].held(), Decimal::TEN);
+        assert_eq!(
+            String::from_utf8(dispute_events).unwrap(),
+            "1,opened,1,1,10.0000
+2,resolved,1,1,10.0000
+3,opened,1,2,10.0000
+"
+        );
+    }
+
+    #[test]
+    fn stops_once_the_processing_time_budget_is_exceeded() {
+        // Hands out a few bytes at a time, taking at least a millisecond for every read
+        struct Throttled<R>(R);
+        impl<R: Read> Read for Throttled<R> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                std::thread::sleep(Duration::from_millis(1));
+                let len = buf.len().min(16);
+                self.0.read(&mut buf[..len])
+            }
+        }
+        let mut csv = String::from("type,client,tx,amount\n");
+        for tx in 1..=1000 {
+            csv.push_str(&format!("deposit,1,{},1\n", tx));
+        }
+
+        let mut accounts = HashMap::new();
+        let mut processing = Processing {
+            deadline: Some(Instant::now() + Duration::from_millis(20)),
+            ..Default::default()
+        };
+        let errors = process_rows(
+            &mut Ledger::default(),
+            &mut accounts,
+            transactions(input_reader(Throttled(csv.as_bytes())), false),
+            &mut processing,
+        );
+
+        // The rows up to the first check past the deadline are applied, the rest is never read
+        assert!(errors.is_empty());
+        assert!(processing.budget_exceeded);
+        assert_eq!(processing.rows, 100);
+        assert_eq!(accounts[&AccountId::Number(1)].total(), Decimal::from(100));
+    }
+}
@@ -0,0 +1,1618 @@
+use crate::account::{Account, AccountId, Balances};
+use crate::diagnostics::Diagnostic;
+use crate::ledger::Ledger;
+use crate::manifest::json_string;
+use crate::money::{checked_sum, reported, reported_at, round_reconciled, REPORTED_SCALE};
+use crate::options::Options;
+use crate::store::AccountStore;
+use crate::transaction::{
+    Outcome, Transaction, TransactionFailure, TransactionId, TransactionType,
+};
+use hmac::{Hmac, Mac};
+use rust_decimal::Decimal;
+use sha2::Sha256;
+use std::io::{self, Write};
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) enum OutputFormat {
+    #[default]
+    Csv,
+    Json,
+    // Column aligned table meant to be read by humans in a terminal
+    Pretty,
+    // Tab separated client id and JSON snapshot pairs
+    Kv,
+}
+
+impl OutputFormat {
+    // Name of the format as specified through the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputFormat::Csv => "csv",
+            OutputFormat::Json => "json",
+            OutputFormat::Pretty => "pretty",
+            OutputFormat::Kv => "kv",
+        }
+    }
+}
+
+// Writer duplicating every byte into each one of its sinks, so the same report can be sent to
+// stdout and archived into files in a single pass
+pub(crate) struct Tee<W: Write> {
+    sinks: Vec<W>,
+}
+
+impl<W: Write> Tee<W> {
+    pub fn new(sinks: Vec<W>) -> Self {
+        Tee { sinks }
+    }
+}
+
+impl<W: Write> Write for Tee<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for sink in &mut self.sinks {
+            sink.write_all(buf)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        for sink in &mut self.sinks {
+            sink.flush()?;
+        }
+        Ok(())
+    }
+}
+
+// Number formatting conventions applied to the amounts of human readable output. Machine formats
+// (CSV, JSON and kv) always use the canonical representation
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum Locale {
+    // 1,234.56
+    EnUs,
+    // 1.234,56
+    DeDe,
+    // 1 234,56
+    FrFr,
+}
+
+// How the amounts of the report are written
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) enum OutputUnits {
+    #[default]
+    Decimal,
+    // Whole numbers of the smallest unit at the scale of the column, such as cents at scale 2
+    Minor,
+}
+
+impl OutputUnits {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "decimal" => Some(OutputUnits::Decimal),
+            "minor" => Some(OutputUnits::Minor),
+            _ => None,
+        }
+    }
+}
+
+impl Locale {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "en-US" | "en_US" => Some(Locale::EnUs),
+            "de-DE" | "de_DE" => Some(Locale::DeDe),
+            "fr-FR" | "fr_FR" => Some(Locale::FrFr),
+            _ => None,
+        }
+    }
+
+    fn separators(&self) -> (char, char) {
+        match self {
+            Locale::EnUs => (',', '.'),
+            Locale::DeDe => ('.', ','),
+            Locale::FrFr => (' ', ','),
+        }
+    }
+
+    pub fn format(&self, amount: Decimal) -> String {
+        let (grouping, decimal_point) = self.separators();
+        let amount = amount.to_string();
+        let (sign, amount) = match amount.strip_prefix('-') {
+            Some(amount) => ("-", amount),
+            None => ("", amount.as_str()),
+        };
+        let (integer, fraction) = match amount.split_once('.') {
+            Some((integer, fraction)) => (integer, Some(fraction)),
+            None => (amount, None),
+        };
+
+        let mut formatted = sign.to_string();
+        for (index, digit) in integer.chars().enumerate() {
+            if index > 0 && (integer.len() - index) % 3 == 0 {
+                formatted.push(grouping);
+            }
+            formatted.push(digit);
+        }
+        if let Some(fraction) = fraction {
+            formatted.push(decimal_point);
+            formatted.push_str(fraction);
+        }
+        formatted
+    }
+}
+
+// Writes the final state of every account in the format selected through the options
+pub(crate) fn write_report(
+    out: &mut impl Write,
+    report: &Report,
+    ledger: &Ledger,
+    options: &Options,
+) -> io::Result<()> {
+    let accounts = report.reported_accounts(options)?;
+    match options.format {
+        OutputFormat::Csv => write_csv(out, &accounts, options),
+        OutputFormat::Json => write_json(out, &accounts, ledger, options),
+        OutputFormat::Pretty => write_pretty(out, &accounts, options),
+        OutputFormat::Kv => write_kv(out, &accounts, options),
+    }
+}
+
+// Accounts in the order they're written into the report
+type ReportedAccounts<'a> = [(AccountId, &'a Account)];
+
+// Amount columns of the report in the order they're written, each one rounded to its own scale,
+// or as the whole number of minor units of that scale
+fn amounts(account: &Account, options: &Options) -> Vec<(&'static str, Decimal)> {
+    exact_amounts(account, options)
+        .into_iter()
+        .map(|(column, amount)| {
+            let scale = scale(column, options);
+            let rounded = reported_at(amount, scale);
+            match options.output_units {
+                OutputUnits::Decimal => (column, rounded),
+                OutputUnits::Minor => {
+                    let mut units = rounded;
+                    units.rescale(scale);
+                    (column, Decimal::from_i128_with_scale(units.mantissa(), 0))
+                }
+            }
+        })
+        .collect()
+}
+
+fn scale(column: &str, options: &Options) -> u32 {
+    options
+        .precision
+        .get(column)
+        .copied()
+        .unwrap_or(REPORTED_SCALE)
+}
+
+// Minor units can't represent a fraction of themselves, so instead of rounding them away the
+// report fails if any amount is more precise than the scale of its column
+fn check_minor_units(accounts: &ReportedAccounts, options: &Options) -> io::Result<()> {
+    for (account_id, account) in accounts {
+        for (column, amount) in exact_amounts(account, options) {
+            let scale = scale(column, options);
+            if amount.normalize().scale() > scale {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{} of client {} is {}, which is not a whole number of minor units at scale {}",
+                        column,
+                        client(*account_id, options),
+                        amount.normalize(),
+                        scale
+                    ),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+// The breakdown columns go right after `held`, which stays the sum of both of them
+fn exact_amounts(account: &Account, options: &Options) -> Vec<(&'static str, Decimal)> {
+    let Balances {
+        available,
+        held,
+        reserved,
+        ..
+    } = account.balances();
+    let mut amounts = vec![("available", available), ("held", held)];
+    if options.held_breakdown {
+        amounts.extend([
+            ("dispute_held", held - reserved),
+            ("reserve_held", reserved),
+        ]);
+    }
+    amounts.push(("total", available + held));
+    amounts
+}
+
+pub(crate) fn columns(options: &Options) -> Vec<&'static str> {
+    let amounts = amounts(&Account::default(), options);
+    std::iter::once("client")
+        .chain(amounts.into_iter().map(|(column, _)| column))
+        .chain(std::iter::once("locked"))
+        .collect()
+}
+
+fn write_pretty(
+    out: &mut impl Write,
+    accounts: &ReportedAccounts,
+    options: &Options,
+) -> io::Result<()> {
+    let header = columns(options)
+        .into_iter()
+        .map(String::from)
+        .collect::<Vec<_>>();
+    let amount = |amount: Decimal| match options.locale {
+        Some(locale) => locale.format(amount),
+        None => amount.to_string(),
+    };
+    let rows = accounts
+        .iter()
+        .map(|(account_id, account)| {
+            std::iter::once(client(*account_id, options))
+                .chain(
+                    amounts(account, options)
+                        .into_iter()
+                        .map(|(_, value)| amount(value)),
+                )
+                .chain(std::iter::once(account.locked().to_string()))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let mut widths = header.iter().map(String::len).collect::<Vec<_>>();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    // Every column is right aligned so the amounts line up on their least significant digit
+    for row in std::iter::once(&header).chain(&rows) {
+        let cells = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:>width$}", cell, width = width))
+            .collect::<Vec<_>>();
+        writeln!(out, "{}", cells.join("  "))?;
+    }
+    Ok(())
+}
+
+fn write_csv(
+    out: &mut impl Write,
+    accounts: &ReportedAccounts,
+    options: &Options,
+) -> io::Result<()> {
+    writeln!(out, "{}", columns(options).join(","))?;
+    for (account_id, account) in accounts {
+        write!(out, "{},", client(*account_id, options))?;
+        for (_, amount) in amounts(account, options) {
+            write!(out, "{},", amount)?;
+        }
+        writeln!(out, "{}", account.locked())?;
+    }
+    Ok(())
+}
+
+// Decimals are rendered as strings so consumers don't lose precision by parsing them as floats
+fn write_json(
+    out: &mut impl Write,
+    accounts: &ReportedAccounts,
+    ledger: &Ledger,
+    options: &Options,
+) -> io::Result<()> {
+    writeln!(out, "[")?;
+    for (index, (account_id, account)) in accounts.iter().enumerate() {
+        let disputes = options
+            .json_verbose
+            .then(|| ledger.open_disputes(*account_id));
+        let separator = if index + 1 < accounts.len() { "," } else { "" };
+        writeln!(
+            out,
+            "  {}{}",
+            account_json(*account_id, account, disputes, options),
+            separator
+        )?;
+    }
+    writeln!(out, "]")
+}
+
+// One `client<TAB>snapshot` line per account, where the snapshot is the same JSON object used by the
+// JSON report, ready to be loaded into a key-value store
+fn write_kv(
+    out: &mut impl Write,
+    accounts: &ReportedAccounts,
+    options: &Options,
+) -> io::Result<()> {
+    for (account_id, account) in accounts {
+        writeln!(
+            out,
+            "{}\t{}",
+            client(*account_id, options),
+            account_json(*account_id, account, None, options)
+        )?;
+    }
+    Ok(())
+}
+
+// Client id as written into the report, numeric ids being zero padded to the --client-width
+fn client(account_id: AccountId, options: &Options) -> String {
+    if let Some(salt) = &options.mask_salt {
+        return masked_client(account_id, salt);
+    }
+    match (account_id, options.client_width) {
+        (AccountId::Number(number), Some(width)) => format!("{:0width$}", number, width = width),
+        _ => account_id.to_string(),
+    }
+}
+
+// Pseudonymous token standing for a client, the HMAC-SHA256 of its id keyed by the salt. The same salt
+// always maps a client to the same token, across runs and platforms, while without the salt the tokens
+// can neither be computed nor told apart from random ones
+fn masked_client(account_id: AccountId, salt: &str) -> String {
+    // HMAC takes keys of any length, so this can't fail
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(salt.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(account_id.to_string().as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn account_json(
+    account_id: AccountId,
+    account: &Account,
+    disputes: Option<Vec<(TransactionId, Decimal)>>,
+    options: &Options,
+) -> String {
+    // Padded and masked clients, and the ones kept as text, are strings which JSON numbers can't
+    // represent
+    let client = match (account_id, options.client_width, &options.mask_salt) {
+        (AccountId::Number(number), None, None) => number.to_string(),
+        _ => json_string(&client(account_id, options)),
+    };
+    let mut json = format!("{{\"client\":{},", client);
+    for (column, amount) in amounts(account, options) {
+        json.push_str(&format!("\"{}\":\"{}\",", column, amount));
+    }
+    json.push_str(&format!("\"locked\":{}", account.locked()));
+    if let Some(disputes) = disputes {
+        let disputes = disputes
+            .iter()
+            .map(|(transaction_id, held)| {
+                format!(
+                    "{{\"tx\":{},\"held\":\"{}\"}}",
+                    transaction_id,
+                    reported(*held)
+                )
+            })
+            .collect::<Vec<_>>();
+        json.push_str(&format!(",\"disputes\":[{}]", disputes.join(",")));
+    }
+    json.push('}');
+    json
+}
+
+pub(crate) fn write_audit_header(out: &mut impl Write) -> io::Result<()> {
+    writeln!(out, "type,client,tx,result,available,held")
+}
+
+// Result of a transaction as written into the audit trail
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum AuditResult {
+    Applied,
+    // Merged duplicate deposits are accepted without moving any funds
+    Ignored,
+    Rejected,
+    // Applied as part of a batch which then failed, so it was reverted
+    RolledBack,
+}
+
+impl AuditResult {
+    pub fn of(result: &Result<Outcome, TransactionFailure>) -> Self {
+        match result {
+            Ok(Outcome::Applied) => AuditResult::Applied,
+            Ok(Outcome::Ignored) => AuditResult::Ignored,
+            Err(_) => AuditResult::Rejected,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            AuditResult::Applied => "applied",
+            AuditResult::Ignored => "ignored",
+            AuditResult::Rejected => "rejected",
+            AuditResult::RolledBack => "rolled_back",
+        }
+    }
+}
+
+// Each audit row shows the balances of the account right after the transaction, so the evolution
+// of an account can be followed line by line. Rejected transactions leave them unchanged
+pub(crate) fn write_audit_row(
+    out: &mut (impl Write + ?Sized),
+    transaction: &Transaction,
+    result: AuditResult,
+    account: Option<&Account>,
+) -> io::Result<()> {
+    let (available, held) = account
+        .map(|account| (account.available(), account.held()))
+        .unwrap_or_default();
+    writeln!(
+        out,
+        "{},{},{},{},{},{}",
+        transaction.transaction_type.name(),
+        transaction.account_id,
+        transaction.transaction_id,
+        result.name(),
+        reported(available),
+        reported(held)
+    )
+}
+
+pub(crate) fn write_dispute_events_header(out: &mut impl Write) -> io::Result<()> {
+    writeln!(out, "sequence,event,client,tx,amount")
+}
+
+// Logs the transition of a dispute into its new state, numbered in the order they were applied.
+// The amount is the one of the disputed deposit or withdrawal
+pub(crate) fn write_dispute_event(
+    out: &mut (impl Write + ?Sized),
+    sequence: u64,
+    transaction: &Transaction,
+    amount: Decimal,
+) -> io::Result<()> {
+    let event = match transaction.transaction_type {
+        TransactionType::Dispute => "opened",
+        TransactionType::Resolve => "resolved",
+        TransactionType::Chargeback => "charged_back",
+        _ => return Ok(()),
+    };
+    writeln!(
+        out,
+        "{},{},{},{},{}",
+        sequence,
+        event,
+        transaction.account_id,
+        transaction.transaction_id,
+        reported(amount)
+    )
+}
+
+// Debugging view of a single account: every transaction applied to it along with the balances it
+// left, followed by the current state of the account and its open disputes
+pub(crate) fn write_account_history(
+    out: &mut impl Write,
+    account_id: AccountId,
+    history: &[(Transaction, Account)],
+    account: Option<&Account>,
+    ledger: &Ledger,
+) -> io::Result<()> {
+    writeln!(out, "History of account #{}", account_id)?;
+    for (transaction, account) in history {
+        let amount = match transaction.transaction_type {
+            TransactionType::Deposit(amount, _)
+            | TransactionType::Withdrawal(amount, _)
+            | TransactionType::ReleaseReserve(amount) => format!(" ${}", amount),
+            TransactionType::Dispute
+            | TransactionType::Resolve
+            | TransactionType::Chargeback
+            | TransactionType::AccrueHeldInterest => String::new(),
+        };
+        writeln!(
+            out,
+            "{} #{}{}: {}",
+            transaction.transaction_type.name(),
+            transaction.transaction_id,
+            amount,
+            balances(account)
+        )?;
+    }
+    match account {
+        Some(account) => writeln!(out, "Current state: {}", balances(account))?,
+        None => writeln!(out, "Current state: the account doesn't exist")?,
+    }
+    for (transaction_id, held) in ledger.open_disputes(account_id) {
+        writeln!(
+            out,
+            "Open dispute: transaction #{} holding ${}",
+            transaction_id,
+            reported(held)
+        )?;
+    }
+    Ok(())
+}
+
+fn balances(account: &Account) -> String {
+    format!(
+        "available {}, held {}, total {}, locked {}",
+        account.available(),
+        account.held(),
+        account.total(),
+        account.locked()
+    )
+}
+
+// Disputes still open once the input is exhausted usually mean rows went missing upstream
+pub(crate) fn write_dangling_disputes(out: &mut impl Write, ledger: &Ledger) -> io::Result<()> {
+    for (transaction_id, account_id, _) in ledger.all_open_disputes() {
+        writeln!(
+            out,
+            "Transaction #{} for account #{} is still disputed",
+            transaction_id, account_id
+        )?;
+    }
+    Ok(())
+}
+
+// Everything processing an input produced, so library callers can render it however they like
+#[derive(Clone, Debug, PartialEq)]
+pub struct Report {
+    // Final state of every account, in ascending client order
+    pub accounts: Vec<(AccountId, Account)>,
+    // Rejected rows in input order, each with the line and the stable kind of its error
+    pub errors: Vec<Diagnostic>,
+    pub stats: ReportStats,
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct ReportStats {
+    pub rows_read: u64,
+    pub transactions_applied: u64,
+}
+
+impl Report {
+    // Report of the accounts alone, without errors nor stats
+    pub(crate) fn from_accounts(accounts: &(impl AccountStore + ?Sized)) -> Self {
+        // Accounts are always reported in ascending client order, so reports of the same input are
+        // identical and pages of the sorted accounts partition every account
+        let mut accounts = accounts
+            .accounts()
+            .map(|(account_id, account)| (*account_id, account.clone()))
+            .collect::<Vec<_>>();
+        accounts.sort_by_key(|(account_id, _)| *account_id);
+        Report {
+            accounts,
+            errors: vec![],
+            stats: ReportStats::default(),
+        }
+    }
+
+    // Writes the accounts exactly like the CSV report of the command line tool run with the same
+    // options, `Options::default()` giving its default report
+    pub fn write_csv(&self, out: &mut impl Write, options: &Options) -> io::Result<()> {
+        write_csv(out, &self.reported_accounts(options)?, options)
+    }
+
+    // The accounts of the selected page
+    fn reported_accounts(&self, options: &Options) -> io::Result<Vec<(AccountId, &Account)>> {
+        let mut accounts = self
+            .accounts
+            .iter()
+            .map(|(account_id, account)| (*account_id, account))
+            .collect::<Vec<_>>();
+        if let Some(page_size) = options.page_size {
+            let page = options.page.unwrap_or(1);
+            let start = (page - 1).saturating_mul(page_size).min(accounts.len());
+            let end = start.saturating_add(page_size).min(accounts.len());
+            accounts = accounts[start..end].to_vec();
+        }
+        if options.output_units == OutputUnits::Minor {
+            check_minor_units(&accounts, options)?;
+        }
+        Ok(accounts)
+    }
+}
+
+// Aggregates computed over the final account map, printed alongside the report when requested
+#[derive(Debug, Default, Eq, PartialEq)]
+pub(crate) struct Summary {
+    pub accounts: usize,
+    pub locked_accounts: usize,
+    pub average_total: Decimal,
+    pub median_total: Decimal,
+    pub largest_held: Decimal,
+}
+
+// The account totals add up to more than a Decimal holds, so an aggregate can't be computed
+#[derive(Debug, thiserror::Error)]
+#[error("The account totals add up to more than the largest amount, so the {0} can't be computed")]
+pub(crate) struct TotalsOverflow(pub &'static str);
+
+impl Summary {
+    pub fn from_accounts(accounts: &(impl AccountStore + ?Sized)) -> Result<Self, TotalsOverflow> {
+        if accounts.is_empty() {
+            return Ok(Summary::default());
+        }
+
+        let mut totals = accounts
+            .accounts()
+            .map(|(_, account)| account.total())
+            .collect::<Vec<_>>();
+        totals.sort();
+        let middle = totals.len() / 2;
+        let median_total = if totals.len() % 2 == 0 {
+            totals[middle - 1]
+                .checked_add(totals[middle])
+                .ok_or(TotalsOverflow("median total"))?
+                / Decimal::TWO
+        } else {
+            totals[middle]
+        };
+        let average_total = totals
+            .iter()
+            .try_fold(Decimal::ZERO, |sum, total| sum.checked_add(*total))
+            .ok_or(TotalsOverflow("average total"))?
+            / Decimal::from(totals.len());
+
+        Ok(Summary {
+            accounts: accounts.len(),
+            locked_accounts: accounts
+                .accounts()
+                .filter(|(_, account)| account.locked())
+                .count(),
+            average_total: reported(average_total),
+            median_total: reported(median_total),
+            largest_held: accounts
+                .accounts()
+                .map(|(_, account)| account.held())
+                .max()
+                .unwrap_or_default(),
+        })
+    }
+}
+
+// Difference between the sum of the account totals rounded one by one, as the report renders them,
+// and the rounded sum of the exact totals. When redistributing, the residual is spread over the
+// accounts with the largest remainder method and the accounts whose total changed are listed
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct RoundingReport {
+    pub rounded_sum: Decimal,
+    pub exact_sum: Decimal,
+    pub residual: Decimal,
+    pub reconciled: Vec<(AccountId, Decimal, Decimal)>,
+}
+
+impl RoundingReport {
+    pub fn from_accounts(
+        accounts: &(impl AccountStore + ?Sized),
+        options: &Options,
+    ) -> Result<Self, TotalsOverflow> {
+        let scale = options
+            .precision
+            .get("total")
+            .copied()
+            .unwrap_or(REPORTED_SCALE);
+        let mut totals = accounts
+            .accounts()
+            .map(|(account_id, account)| {
+                let Balances {
+                    available, held, ..
+                } = account.balances();
+                (*account_id, available + held)
+            })
+            .collect::<Vec<_>>();
+        // Ties of the redistribution go to the lowest client ids
+        totals.sort_by_key(|(account_id, _)| *account_id);
+        let rounded = totals
+            .iter()
+            .map(|(_, total)| reported_at(*total, scale))
+            .collect::<Vec<_>>();
+        let rounded_sum =
+            checked_sum(rounded.iter().copied()).ok_or(TotalsOverflow("rounded sum"))?;
+        let exact_sum = reported_at(
+            checked_sum(totals.iter().map(|(_, total)| *total))
+                .ok_or(TotalsOverflow("exact sum"))?,
+            scale,
+        );
+
+        let mut reconciled = vec![];
+        if options.redistribute_residual {
+            let exact = totals.iter().map(|(_, total)| *total).collect::<Vec<_>>();
+            for (((account_id, _), rounded), reconciled_total) in totals
+                .iter()
+                .zip(&rounded)
+                .zip(round_reconciled(&exact, scale).ok_or(TotalsOverflow("reconciled totals"))?)
+            {
+                if *rounded != reconciled_total {
+                    reconciled.push((*account_id, *rounded, reconciled_total));
+                }
+            }
+        }
+        let residual = rounded_sum
+            .checked_sub(exact_sum)
+            .ok_or(TotalsOverflow("residual"))?;
+        Ok(RoundingReport {
+            rounded_sum,
+            exact_sum,
+            residual: reported_at(residual, scale),
+            reconciled,
+        })
+    }
+}
+
+pub(crate) fn write_rounding_report(
+    out: &mut impl Write,
+    report: &RoundingReport,
+) -> io::Result<()> {
+    writeln!(out, "sum of rounded totals: {}", report.rounded_sum)?;
+    writeln!(out, "rounded sum of totals: {}", report.exact_sum)?;
+    writeln!(out, "residual: {}", report.residual)?;
+    for (account_id, rounded, reconciled) in &report.reconciled {
+        writeln!(
+            out,
+            "total of client {} reconciled from {} to {}",
+            account_id, rounded, reconciled
+        )?;
+    }
+    Ok(())
+}
+
+pub(crate) fn write_summary(out: &mut impl Write, summary: &Summary) -> io::Result<()> {
+    writeln!(out, "accounts: {}", summary.accounts)?;
+    writeln!(out, "locked accounts: {}", summary.locked_accounts)?;
+    writeln!(out, "average total: {}", summary.average_total)?;
+    writeln!(out, "median total: {}", summary.median_total)?;
+    writeln!(out, "largest held: {}", summary.largest_held)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::ledger::{Ledger, LedgerOptions};
+    use crate::options::Options;
+    use crate::processing::tests::{process_csv, process_csv_with_ledger};
+    use crate::processing::{process_rows, Processing};
+    use crate::report::{
+        masked_client, write_account_history, write_dangling_disputes, write_report, Locale,
+        OutputFormat, Report, RoundingReport, Summary, Tee,
+    };
+    use crate::transaction::{DepositState, TransactionId, TransactionType};
+    use crate::{AccountId, Transaction};
+    use anyhow::Error;
+    use csv::{ReaderBuilder, Trim};
+    use rust_decimal::Decimal;
+    use serde_json::Value;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::io::Write;
+    use std::path::Path;
+    use std::rc::Rc;
+
+    #[test]
+    fn json_verbose_output_nests_open_disputes() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,1.5
+                        deposit,1,2,2
+                        deposit,1,3,0.25
+                        dispute,1,1,
+                        dispute,1,2,
+                        resolve,1,2,
+                        dispute,1,3";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let mut ledger = Ledger::default();
+        let (accounts, errors) = process_csv_with_ledger(&mut ledger, csv);
+        let options = Options {
+            format: OutputFormat::Json,
+            json_verbose: true,
+            ..Default::default()
+        };
+        let mut output = vec![];
+        write_report(
+            &mut output,
+            &Report::from_accounts(&accounts),
+            &ledger,
+            &options,
+        )
+        .unwrap();
+
+        assert_eq!(errors.len(), 0);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "[\n  {\"client\":1,\"available\":\"2.0000\",\"held\":\"1.7500\",\"total\":\"3.7500\",\"locked\":false,\
+            \"disputes\":[{\"tx\":1,\"held\":\"1.5000\"},{\"tx\":3,\"held\":\"0.2500\"}]}\n]\n"
+        );
+    }
+
+    #[test]
+    fn summarizes_final_accounts() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,1
+                        deposit,2,2,2.5
+                        deposit,3,3,4
+                        deposit,4,4,10
+                        dispute,2,2,
+                        dispute,3,3,
+                        chargeback,3,3,
+                        withdrawal,4,5,3";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let (accounts, errors) = process_csv(csv);
+        assert_eq!(errors.len(), 0);
+        assert_eq!(
+            Summary::from_accounts(&accounts).unwrap(),
+            Summary {
+                accounts: 4,
+                locked_accounts: 1,
+                // Totals are 1, 2.5, 0 and 7
+                average_total: Decimal::from_str_exact("2.625").unwrap(),
+                median_total: Decimal::from_str_exact("1.75").unwrap(),
+                largest_held: Decimal::from_str_exact("2.5").unwrap(),
+            }
+        );
+    }
+
+    #[test]
+    fn fails_to_summarize_totals_overflowing_their_sum() {
+        let large = Decimal::MAX - Decimal::ONE_HUNDRED;
+        let deposit = |client, tx| {
+            Ok::<_, Error>(Transaction::new(
+                TransactionType::Deposit(large, DepositState::Deposited),
+                AccountId::Number(client),
+                TransactionId(tx),
+            ))
+        };
+        let mut accounts = HashMap::new();
+        process_rows(
+            &mut Ledger::default(),
+            &mut accounts,
+            vec![deposit(1, 1), deposit(2, 2), deposit(3, 3)],
+            &mut Processing::default(),
+        );
+
+        // The median of an odd amount of accounts needs no sum, unlike the average
+        assert_eq!(
+            Summary::from_accounts(&accounts).unwrap_err().to_string(),
+            "The account totals add up to more than the largest amount, so the average total can't be computed"
+        );
+        accounts.remove(&AccountId::Number(3));
+        assert_eq!(
+            Summary::from_accounts(&accounts).unwrap_err().to_string(),
+            "The account totals add up to more than the largest amount, so the median total can't be computed"
+        );
+    }
+
+    #[test]
+    fn tee_writes_identical_report_to_every_sink() {
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_path(Path::new("tests/basic.csv"))
+            .unwrap();
+        let mut ledger = Ledger::default();
+        let (accounts, _) = process_csv_with_ledger(&mut ledger, csv);
+
+        let (mut first, mut second) = (vec![], vec![]);
+        let mut tee = Tee::new(vec![&mut first, &mut second]);
+        write_report(
+            &mut tee,
+            &Report::from_accounts(&accounts),
+            &ledger,
+            &Options::default(),
+        )
+        .unwrap();
+        tee.flush().unwrap();
+
+        let mut direct = vec![];
+        write_report(
+            &mut direct,
+            &Report::from_accounts(&accounts),
+            &ledger,
+            &Options::default(),
+        )
+        .unwrap();
+        assert_eq!(first, second);
+        assert_eq!(first, direct);
+    }
+
+    #[test]
+    fn piped_output_defaults_to_csv() {
+        let args = ["transaction_processor", "tests/basic.csv"].map(String::from);
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_path(Path::new("tests/basic.csv"))
+            .unwrap();
+        let mut ledger = Ledger::default();
+        let (accounts, _) = process_csv_with_ledger(&mut ledger, csv);
+
+        let mut piped = Options::parse(&args).unwrap();
+        piped.apply_terminal_defaults(false);
+        assert_eq!(piped.format, OutputFormat::Csv);
+        let mut output = vec![];
+        write_report(
+            &mut output,
+            &Report::from_accounts(&accounts),
+            &ledger,
+            &piped,
+        )
+        .unwrap();
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .starts_with("client,available,held,total,locked\n"));
+
+        let mut interactive = Options::parse(&args).unwrap();
+        interactive.apply_terminal_defaults(true);
+        assert_eq!(interactive.format, OutputFormat::Pretty);
+
+        let forced_args = [
+            "transaction_processor",
+            "--format",
+            "csv",
+            "tests/basic.csv",
+        ];
+        let mut forced = Options::parse(&forced_args.map(String::from)).unwrap();
+        forced.apply_terminal_defaults(true);
+        assert_eq!(forced.format, OutputFormat::Csv);
+    }
+
+    #[test]
+    fn kv_output_emits_one_json_snapshot_per_client() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,1.5
+                        deposit,2,2,2
+                        dispute,2,2";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let mut ledger = Ledger::default();
+        let (accounts, _) = process_csv_with_ledger(&mut ledger, csv);
+        let options = Options {
+            format: OutputFormat::Kv,
+            ..Default::default()
+        };
+        let mut output = vec![];
+        write_report(
+            &mut output,
+            &Report::from_accounts(&accounts),
+            &ledger,
+            &options,
+        )
+        .unwrap();
+
+        let mut lines = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect::<Vec<_>>();
+        lines.sort();
+        assert_eq!(
+            lines,
+            vec![
+                "1\t{\"client\":1,\"available\":\"1.5000\",\"held\":\"0.0000\",\"total\":\"1.5000\",\"locked\":false}",
+                "2\t{\"client\":2,\"available\":\"0.0000\",\"held\":\"2.0000\",\"total\":\"2.0000\",\"locked\":false}",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_only_disputes_left_open() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,1
+                        deposit,2,2,2
+                        dispute,1,1,
+                        dispute,2,2,
+                        resolve,1,1";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let mut ledger = Ledger::default();
+        let (_, errors) = process_csv_with_ledger(&mut ledger, csv);
+        let mut output = vec![];
+        write_dangling_disputes(&mut output, &ledger).unwrap();
+
+        assert_eq!(errors.len(), 0);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "Transaction #2 for account #2 is still disputed\n"
+        );
+    }
+
+    #[test]
+    fn locale_only_affects_pretty_output() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,1234567.5
+                        withdrawal,1,2,0.25";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+        let mut ledger = Ledger::default();
+        let (accounts, _) = process_csv_with_ledger(&mut ledger, csv);
+        let render = |format| {
+            let options = Options {
+                format,
+                locale: Locale::parse("de-DE"),
+                ..Default::default()
+            };
+            let mut output = vec![];
+            write_report(
+                &mut output,
+                &Report::from_accounts(&accounts),
+                &ledger,
+                &options,
+            )
+            .unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        assert_eq!(
+            render(OutputFormat::Pretty),
+            "client       available    held           total  locked
+     1  1.234.567,2500  0,0000  1.234.567,2500   false
+"
+        );
+        assert_eq!(
+            render(OutputFormat::Csv),
+            "client,available,held,total,locked\n1,1234567.2500,0.0000,1234567.2500,false\n"
+        );
+        assert_eq!(Locale::EnUs.format(Decimal::from(-1234)), "-1,234");
+        assert_eq!(
+            Locale::FrFr.format(Decimal::from_str_exact("123456.7").unwrap()),
+            "123 456,7"
+        );
+    }
+
+    #[test]
+    fn held_breakdown_splits_disputes_and_reserve() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,100
+                        deposit,1,2,20
+                        dispute,1,2,";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let mut ledger = Ledger::with_options(LedgerOptions {
+            deposit_reserve_pct: Some(Decimal::TEN),
+            ..Default::default()
+        });
+        let (accounts, errors) = process_csv_with_ledger(&mut ledger, csv);
+        let options = Options {
+            held_breakdown: true,
+            ..Default::default()
+        };
+        let mut output = vec![];
+        write_report(
+            &mut output,
+            &Report::from_accounts(&accounts),
+            &ledger,
+            &options,
+        )
+        .unwrap();
+
+        assert_eq!(errors.len(), 0);
+        // 20 held by the dispute and 12 reserved out of both deposits add up to the 32 held
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,dispute_held,reserve_held,total,locked\n1,88.0000,32.0000,20.0000,12.0000,120.0000,false\n"
+        );
+    }
+
+    #[test]
+    fn explains_the_history_of_an_account() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,10
+                        deposit,2,2,7
+                        deposit,1,3,5
+                        withdrawal,1,4,3
+                        withdrawal,1,5,30
+                        dispute,1,3,";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let history = Rc::new(RefCell::new(vec![]));
+        let mut ledger = Ledger::default();
+        let recorded = history.clone();
+        ledger.on_account_change(AccountId::Number(1), move |transaction, account| {
+            recorded.borrow_mut().push((*transaction, account.clone()))
+        });
+        let (accounts, errors) = process_csv_with_ledger(&mut ledger, csv);
+        let mut output = vec![];
+        write_account_history(
+            &mut output,
+            AccountId::Number(1),
+            &history.borrow(),
+            accounts.get(&AccountId::Number(1)),
+            &ledger,
+        )
+        .unwrap();
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "History of account #1
+deposit #1 $10: available 10.0000, held 0.0000, total 10.0000, locked false
+deposit #3 $5: available 15.0000, held 0.0000, total 15.0000, locked false
+withdrawal #4 $3: available 12.0000, held 0.0000, total 12.0000, locked false
+dispute #3: available 7.0000, held 5.0000, total 12.0000, locked false
+Current state: available 7.0000, held 5.0000, total 12.0000, locked false
+Open dispute: transaction #3 holding $5.0000
+"
+        );
+    }
+
+    #[test]
+    fn renders_each_column_at_its_configured_precision() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,10.12345
+                        deposit,1,2,2.00991
+                        dispute,1,2,";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let mut ledger = Ledger::default();
+        let (accounts, errors) = process_csv_with_ledger(&mut ledger, csv);
+        let options = Options::parse(&[
+            "transaction_processor".to_string(),
+            "--precision".to_string(),
+            "total=2,available=1".to_string(),
+            "input.csv".to_string(),
+        ])
+        .unwrap();
+        let mut output = vec![];
+        write_report(
+            &mut output,
+            &Report::from_accounts(&accounts),
+            &ledger,
+            &options,
+        )
+        .unwrap();
+
+        // Held keeps the default 4 decimal places, and the total is rounded from the exact balances
+        assert_eq!(errors.len(), 0);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n1,10.1,2.0099,12.13,false\n"
+        );
+        assert_eq!(
+            Options::parse(&[
+                "transaction_processor".to_string(),
+                "--precision".to_string(),
+                "locked=2".to_string(),
+                "input.csv".to_string(),
+            ])
+            .unwrap_err()
+            .to_string(),
+            "locked is not an amount column of the report"
+        );
+    }
+
+    #[test]
+    fn paginates_the_accounts_sorted_by_client() {
+        let csv = "type,client,tx,amount
+                        deposit,4,1,4
+                        deposit,2,2,2
+                        deposit,5,3,5
+                        deposit,1,4,1
+                        deposit,3,5,3";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let mut ledger = Ledger::default();
+        let (accounts, _) = process_csv_with_ledger(&mut ledger, csv);
+        let page = |page: usize| {
+            let options = Options::parse(&[
+                "transaction_processor".to_string(),
+                "--page-size".to_string(),
+                "2".to_string(),
+                "--page".to_string(),
+                page.to_string(),
+                "input.csv".to_string(),
+            ])
+            .unwrap();
+            let mut output = vec![];
+            write_report(
+                &mut output,
+                &Report::from_accounts(&accounts),
+                &ledger,
+                &options,
+            )
+            .unwrap();
+            let output = String::from_utf8(output).unwrap();
+            let mut lines = output.lines();
+            assert_eq!(lines.next(), Some("client,available,held,total,locked"));
+            lines
+                .map(|line| line.split(',').next().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(page(2), ["3", "4"]);
+        // Out of range pages only have the header
+        assert!(page(4).is_empty());
+        let pages = (1..=3).flat_map(page).collect::<Vec<_>>();
+        assert_eq!(pages, ["1", "2", "3", "4", "5"]);
+    }
+
+    #[test]
+    fn pads_client_ids_to_the_client_width() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,1
+                        deposit,65535,2,2";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let mut ledger = Ledger::default();
+        let (accounts, _) = process_csv_with_ledger(&mut ledger, csv);
+        let report = |format: &str| {
+            let options = Options::parse(&[
+                "transaction_processor".to_string(),
+                "--client-width".to_string(),
+                "5".to_string(),
+                "--format".to_string(),
+                format.to_string(),
+                "input.csv".to_string(),
+            ])
+            .unwrap();
+            let mut output = vec![];
+            write_report(
+                &mut output,
+                &Report::from_accounts(&accounts),
+                &ledger,
+                &options,
+            )
+            .unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        let csv = report("csv");
+        assert!(csv.lines().any(|line| line.starts_with("00001,")));
+        assert!(csv.lines().any(|line| line.starts_with("65535,")));
+        assert!(report("json").contains("{\"client\":\"00001\","));
+    }
+
+    #[test]
+    fn redistributes_the_rounding_residual() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,0.3333
+                        deposit,2,2,0.3333
+                        deposit,3,3,0.3333";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let (accounts, _) = process_csv(csv);
+        let report = |flags: &[&str]| {
+            let args = ["transaction_processor"]
+                .iter()
+                .chain(flags)
+                .chain(&["input.csv"])
+                .map(|arg| arg.to_string())
+                .collect::<Vec<_>>();
+            RoundingReport::from_accounts(&accounts, &Options::parse(&args).unwrap()).unwrap()
+        };
+        let decimal = |value| Decimal::from_str_exact(value).unwrap();
+
+        let residual = report(&["--rounding-report", "--precision", "total=2"]);
+        assert_eq!(residual.rounded_sum, decimal("0.99"));
+        assert_eq!(residual.exact_sum, Decimal::ONE);
+        assert_eq!(residual.residual, decimal("-0.01"));
+        assert!(residual.reconciled.is_empty());
+
+        let redistributed = report(&[
+            "--rounding-report",
+            "--redistribute-residual",
+            "--precision",
+            "total=2",
+        ]);
+        assert_eq!(
+            redistributed.reconciled,
+            [(AccountId::Number(1), decimal("0.33"), decimal("0.34"))]
+        );
+    }
+
+    #[test]
+    fn fails_the_rounding_report_when_the_totals_overflow() {
+        let large = Decimal::MAX - Decimal::ONE_HUNDRED;
+        let deposit = |client, tx| {
+            Ok::<_, Error>(Transaction::new(
+                TransactionType::Deposit(large, DepositState::Deposited),
+                AccountId::Number(client),
+                TransactionId(tx),
+            ))
+        };
+        let mut accounts = HashMap::new();
+        process_rows(
+            &mut Ledger::default(),
+            &mut accounts,
+            vec![deposit(1, 1), deposit(2, 2)],
+            &mut Processing::default(),
+        );
+        let options = Options::parse(&[
+            "transaction_processor".to_string(),
+            "--rounding-report".to_string(),
+            "input.csv".to_string(),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            RoundingReport::from_accounts(&accounts, &options)
+                .unwrap_err()
+                .to_string(),
+            "The account totals add up to more than the largest amount, so the rounded sum can't be computed"
+        );
+    }
+
+    #[test]
+    fn json_output_round_trips_into_the_balances() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,1.23456
+                        deposit,2,2,3
+                        withdrawal,2,3,0.5
+                        dispute,1,1,
+                        deposit,3,4,7
+                        dispute,3,4,
+                        chargeback,3,4,";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let mut ledger = Ledger::default();
+        let (accounts, _) = process_csv_with_ledger(&mut ledger, csv);
+        let options = Options {
+            format: OutputFormat::Json,
+            ..Default::default()
+        };
+        let mut output = vec![];
+        write_report(
+            &mut output,
+            &Report::from_accounts(&accounts),
+            &ledger,
+            &options,
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!((lines[0], lines[lines.len() - 1]), ("[", "]"));
+        let objects = lines[1..lines.len() - 1]
+            .iter()
+            .map(|line| serde_json::from_str::<Value>(line.trim_end_matches(',')).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(objects.len(), accounts.len());
+        for object in objects {
+            let account =
+                &accounts[&AccountId::Number(object["client"].to_string().parse().unwrap())];
+            let amount =
+                |field: &str| Decimal::from_str_exact(object[field].as_str().unwrap()).unwrap();
+            // Amounts are strings holding the same 4 decimal places as the CSV report
+            assert_eq!(amount("available"), account.available());
+            assert_eq!(amount("held"), account.held());
+            assert_eq!(amount("total"), account.total());
+            assert_eq!(object["locked"], account.locked());
+        }
+        assert!(output.contains("\"held\":\"1.2346\""));
+    }
+
+    #[test]
+    fn masks_client_ids_with_salted_tokens() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,1.5
+                        deposit,2,2,2";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let mut ledger = Ledger::default();
+        let (accounts, _) = process_csv_with_ledger(&mut ledger, csv);
+        // Every run writes the report from scratch, parsing its own options
+        let run = |salt: &str| {
+            let options = Options::parse(&[
+                "transaction_processor".to_string(),
+                "--mask-clients".to_string(),
+                "--mask-salt".to_string(),
+                salt.to_string(),
+                "input.csv".to_string(),
+            ])
+            .unwrap();
+            let mut output = vec![];
+            write_report(
+                &mut output,
+                &Report::from_accounts(&accounts),
+                &ledger,
+                &options,
+            )
+            .unwrap();
+            let output = String::from_utf8(output).unwrap();
+            output
+                .lines()
+                .skip(1)
+                .map(|line| {
+                    let (client, balances) = line.split_once(',').unwrap();
+                    (balances.to_string(), client.to_string())
+                })
+                .collect::<HashMap<_, _>>()
+        };
+
+        let (first, second) = (run("pepper"), run("pepper"));
+        let token = &first["1.5000,0.0000,1.5000,false"];
+        assert_eq!(token.len(), 64);
+        assert_ne!(token, "1");
+        assert_eq!(&second["1.5000,0.0000,1.5000,false"], token);
+        assert_ne!(&first["2.0000,0.0000,2.0000,false"], token);
+        assert_ne!(&run("salt")["1.5000,0.0000,1.5000,false"], token);
+    }
+
+    #[test]
+    fn masks_adjacent_client_ids_with_unrelated_tokens() {
+        let bits = |token: &str| {
+            (0..token.len())
+                .step_by(2)
+                .map(|index| u8::from_str_radix(&token[index..index + 2], 16).unwrap())
+                .collect::<Vec<_>>()
+        };
+        let first = masked_client(AccountId::Number(1), "pepper");
+        let second = masked_client(AccountId::Number(2), "pepper");
+        // Unrelated tokens differ in about half their bits, and don't share a prefix
+        let differing: u32 = bits(&first)
+            .iter()
+            .zip(bits(&second))
+            .map(|(first, second)| (first ^ second).count_ones())
+            .sum();
+        assert!(differing > 80, "only {} bits differ", differing);
+        assert_ne!(first[..4], second[..4]);
+    }
+
+    #[test]
+    fn reports_accounts_sorted_by_client() {
+        let csv = "type,client,tx,amount
+                        deposit,3,1,3
+                        deposit,1,2,1
+                        deposit,2,3,2";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let mut ledger = Ledger::default();
+        let (accounts, _) = process_csv_with_ledger(&mut ledger, csv);
+        let mut output = vec![];
+        write_report(
+            &mut output,
+            &Report::from_accounts(&accounts),
+            &ledger,
+            &Options::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n1,1.0000,0.0000,1.0000,false\n2,2.0000,0.0000,2.0000,false\n3,3.0000,0.0000,3.0000,false\n"
+        );
+    }
+
+    #[test]
+    fn stores_and_reports_amounts_at_4_decimal_places() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,1.00005
+                        deposit,2,2,1.00015
+                        deposit,3,3,1";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let mut ledger = Ledger::default();
+        let (accounts, errors) = process_csv_with_ledger(&mut ledger, csv);
+        let mut output = vec![];
+        write_report(
+            &mut output,
+            &Report::from_accounts(&accounts),
+            &ledger,
+            &Options::default(),
+        )
+        .unwrap();
+
+        assert_eq!(errors.len(), 0);
+        // Ties are rounded to the even digit when the amounts are stored
+        assert_eq!(
+            accounts.get(&AccountId::Number(1)).unwrap().total(),
+            Decimal::ONE
+        );
+        assert_eq!(
+            accounts.get(&AccountId::Number(2)).unwrap().total(),
+            Decimal::from_str_exact("1.0002").unwrap()
+        );
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n1,1.0000,0.0000,1.0000,false\n\
+            2,1.0002,0.0000,1.0002,false\n3,1.0000,0.0000,1.0000,false\n"
+        );
+    }
+
+    #[test]
+    fn writes_amounts_as_minor_units() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,1.2345
+                        deposit,2,2,3.5";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let mut ledger = Ledger::default();
+        let (accounts, errors) = process_csv_with_ledger(&mut ledger, csv);
+        assert!(errors.is_empty());
+        let report = |args: &[&str]| {
+            let args = std::iter::once("transaction_processor")
+                .chain(args.iter().copied())
+                .chain(std::iter::once("input.csv"))
+                .map(String::from)
+                .collect::<Vec<_>>();
+            let mut output = vec![];
+            write_report(
+                &mut output,
+                &Report::from_accounts(&accounts),
+                &ledger,
+                &Options::parse(&args).unwrap(),
+            )
+            .map(|()| String::from_utf8(output).unwrap())
+            .map_err(|e| e.to_string())
+        };
+
+        let output = report(&["--output-units", "minor"]).unwrap();
+        let mut lines = output.lines().collect::<Vec<_>>();
+        lines[1..].sort();
+        assert_eq!(
+            lines,
+            [
+                "client,available,held,total,locked",
+                "1,12345,0,12345,false",
+                "2,35000,0,35000,false"
+            ]
+        );
+        // Cents can't represent the balance of the first client
+        assert_eq!(
+            report(&["--output-units", "minor", "--precision", "available=2"]).unwrap_err(),
+            "available of client 1 is 1.2345, which is not a whole number of minor units at scale 2"
+        );
+    }
+}
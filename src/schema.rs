@@ -0,0 +1,274 @@
+use crate::manifest::json_string;
+use crate::options::Options;
+use crate::report::{columns, OutputFormat, OutputUnits};
+use crate::transaction::{AMOUNT_TYPES, TRANSACTION_TYPES};
+use crate::{input_reader, transactions};
+use anyhow::{anyhow, Error};
+use std::io::{self, Read, Write};
+
+// A column of the input or of the report, along with what it holds
+struct Column {
+    name: &'static str,
+    column_type: &'static str,
+    description: &'static str,
+}
+
+impl Column {
+    fn json(&self) -> String {
+        format!(
+            "{{\"name\":{},\"type\":{},\"description\":{}}}",
+            json_string(self.name),
+            json_string(self.column_type),
+            json_string(self.description)
+        )
+    }
+}
+
+fn client_type(options: &Options) -> &'static str {
+    if options.string_client_ids {
+        "string"
+    } else {
+        "integer"
+    }
+}
+
+fn input_columns(options: &Options) -> Vec<Column> {
+    vec![
+        Column {
+            name: "type",
+            column_type: "string",
+            description: "Type of the transaction, one of the transaction types",
+        },
+        Column {
+            name: "client",
+            column_type: client_type(options),
+            description: "Client whose account the transaction applies to",
+        },
+        Column {
+            name: "tx",
+            column_type: "integer",
+            description: "Globally unique id of a deposit or withdrawal, or the id of the \
+                transaction referenced by a dispute, resolve or chargeback",
+        },
+        Column {
+            name: "amount",
+            column_type: "decimal",
+            description: "Positive amount, only required by the transaction types which take one",
+        },
+    ]
+}
+
+// Columns which can be left out of the input altogether
+fn optional_input_columns() -> Vec<Column> {
+    vec![Column {
+        name: "batch",
+        column_type: "integer",
+        description: "Consecutive rows sharing a batch id are applied atomically, if one of them \
+            fails the whole batch is rolled back",
+    }]
+}
+
+// The report columns come from the report itself, so optional columns are listed exactly when
+// they're written
+fn output_columns(options: &Options) -> Vec<Column> {
+    let mut output_columns = columns(options)
+        .into_iter()
+        .map(|name| {
+            let amount_type = match options.output_units {
+                OutputUnits::Decimal => "decimal",
+                OutputUnits::Minor => "integer",
+            };
+            let (column_type, description) = match name {
+                "client" => (client_type(options), "Client owning the account"),
+                "available" => (amount_type, "Funds available for withdrawal"),
+                "held" => (
+                    amount_type,
+                    "Funds held by disputes and the deposit reserve",
+                ),
+                "dispute_held" => (amount_type, "Funds held by open disputes"),
+                "reserve_held" => (amount_type, "Funds held by the deposit reserve"),
+                "total" => (amount_type, "Sum of the available and held funds"),
+                "locked" => ("boolean", "Whether a chargeback locked the account"),
+                _ => ("string", ""),
+            };
+            Column {
+                name,
+                column_type,
+                description,
+            }
+        })
+        .collect::<Vec<_>>();
+    if options.json_verbose {
+        output_columns.push(Column {
+            name: "disputes",
+            column_type: "array",
+            description: "Open disputes of the account, with their tx and held amount",
+        });
+    }
+    output_columns
+}
+
+// Writes a JSON description of the CSV input and of the report under the specified options
+pub(crate) fn write_schema(out: &mut impl Write, options: &Options) -> io::Result<()> {
+    let json_columns = |columns: Vec<Column>| {
+        columns
+            .iter()
+            .map(Column::json)
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+    let transaction_types = TRANSACTION_TYPES
+        .iter()
+        .map(|name| {
+            format!(
+                "{{\"name\":{},\"amount\":{}}}",
+                json_string(name),
+                AMOUNT_TYPES.contains(name)
+            )
+        })
+        .collect::<Vec<_>>();
+    // JSON reports render decimals as strings so they're never rounded by float parsers
+    let decimals = match options.format {
+        OutputFormat::Json | OutputFormat::Kv => "string",
+        OutputFormat::Csv | OutputFormat::Pretty => "number",
+    };
+    writeln!(
+        out,
+        "{{\"input\":{{\"format\":\"csv\",\"columns\":[{}],\"optional_columns\":[{}],\"transaction_types\":[{}]}},\
+        \"output\":{{\"format\":\"{}\",\"decimals\":\"{}\",\"columns\":[{}]}}}}",
+        json_columns(input_columns(options)),
+        json_columns(optional_input_columns()),
+        transaction_types.join(","),
+        options.format.name(),
+        decimals,
+        json_columns(output_columns(options))
+    )
+}
+
+// Checks the header and the first rows of the input without processing any of them, returning
+// every problem found. Only the rows which are checked get read, so it stays quick on huge inputs
+pub(crate) fn validate_schema(
+    input: impl Read,
+    rows: usize,
+    options: &Options,
+) -> Result<Vec<Error>, Error> {
+    let mut reader = input_reader(input);
+    let headers = reader.headers()?.clone();
+    let mut problems = vec![];
+    let known_columns = input_columns(options)
+        .into_iter()
+        .chain(optional_input_columns())
+        .map(|column| column.name)
+        .collect::<Vec<_>>();
+    for column in input_columns(options) {
+        if !headers.iter().any(|header| header == column.name) {
+            problems.push(anyhow!("The header is missing the {} column", column.name));
+        }
+    }
+    for header in headers.iter() {
+        if !known_columns.contains(&header) {
+            problems.push(anyhow!("{} is not a column of the input", header));
+        }
+    }
+    // Rows can't be mapped to columns without a valid header
+    if problems.is_empty() {
+        problems.extend(
+            transactions(reader, options.string_client_ids)
+                .take(rows)
+                .filter_map(Result::err),
+        );
+    }
+    Ok(problems)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::options::Options;
+    use crate::schema::{validate_schema, write_schema};
+
+    #[test]
+    fn emits_the_input_and_output_schemas() {
+        let schema = |args: &[&str]| {
+            let args = std::iter::once("transaction_processor")
+                .chain(args.iter().copied())
+                .map(String::from)
+                .collect::<Vec<_>>();
+            let mut output = vec![];
+            write_schema(&mut output, &Options::parse(&args).unwrap()).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        let column_names = |section: &str| {
+            section
+                .split("{\"name\":\"")
+                .skip(1)
+                .map(|column| column.split('"').next().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let default = schema(&["--emit-schema"]);
+        let (input, output) = default.split_once("\"output\"").unwrap();
+        let (input_columns, optional_columns) = input.split_once("\"optional_columns\"").unwrap();
+        assert_eq!(
+            column_names(input_columns),
+            ["type", "client", "tx", "amount"]
+        );
+        assert_eq!(
+            column_names(output),
+            ["client", "available", "held", "total", "locked"]
+        );
+        assert!(optional_columns.starts_with(":[{\"name\":\"batch\""));
+        assert!(default.contains("{\"name\":\"dispute\",\"amount\":false}"));
+
+        // Optional columns are only listed when they're enabled
+        let breakdown = schema(&["--emit-schema", "--held-breakdown", "--string-client-ids"]);
+        let (input, output) = breakdown.split_once("\"output\"").unwrap();
+        assert!(input.contains("{\"name\":\"client\",\"type\":\"string\""));
+        assert_eq!(
+            column_names(output),
+            [
+                "client",
+                "available",
+                "held",
+                "dispute_held",
+                "reserve_held",
+                "total",
+                "locked"
+            ]
+        );
+    }
+
+    #[test]
+    fn validates_the_schema_from_the_first_rows_only() {
+        let options = Options::parse(&[
+            "transaction_processor".to_string(),
+            "--validate-only-schema".to_string(),
+            "2".to_string(),
+            "input.csv".to_string(),
+        ])
+        .unwrap();
+        let problems = |csv: &str| {
+            validate_schema(csv.as_bytes(), 2, &options)
+                .unwrap()
+                .iter()
+                .map(|problem| problem.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            problems("kind,client,tx,amount\ndeposit,1,1,1\n"),
+            [
+                "The header is missing the type column",
+                "kind is not a column of the input"
+            ]
+        );
+        // Rows past the first two are never read
+        assert!(
+            problems("type,client,tx,amount\ndeposit,1,1,1\ndeposit,1,2,1\nbogus\n").is_empty()
+        );
+        assert_eq!(
+            problems("type,client,tx,amount\ndeposit,1,1,1\ndeposit,x,2,1\n").len(),
+            1
+        );
+    }
+}
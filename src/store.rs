@@ -0,0 +1,112 @@
+use crate::account::{Account, AccountId};
+use std::collections::{BTreeMap, HashMap};
+
+// Where the ledger keeps the accounts, so the processing works with any map from client to
+// account. A HashMap is usually faster for scattered client ids while a BTreeMap keeps the
+// accounts ordered by client, see the accounts_store benchmark
+pub trait AccountStore {
+    fn account(&self, account_id: &AccountId) -> Option<&Account>;
+
+    fn account_mut(&mut self, account_id: &AccountId) -> Option<&mut Account>;
+
+    // The account of the client, which is created empty if it doesn't exist yet
+    fn open_account(&mut self, account_id: AccountId) -> &mut Account;
+
+    fn insert_account(&mut self, account_id: AccountId, account: Account);
+
+    fn remove_account(&mut self, account_id: &AccountId);
+
+    // Every account, in the order the store keeps them
+    fn accounts(&self) -> Box<dyn Iterator<Item = (&AccountId, &Account)> + '_>;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl AccountStore for HashMap<AccountId, Account> {
+    fn account(&self, account_id: &AccountId) -> Option<&Account> {
+        self.get(account_id)
+    }
+
+    fn account_mut(&mut self, account_id: &AccountId) -> Option<&mut Account> {
+        self.get_mut(account_id)
+    }
+
+    fn open_account(&mut self, account_id: AccountId) -> &mut Account {
+        self.entry(account_id).or_default()
+    }
+
+    fn insert_account(&mut self, account_id: AccountId, account: Account) {
+        self.insert(account_id, account);
+    }
+
+    fn remove_account(&mut self, account_id: &AccountId) {
+        self.remove(account_id);
+    }
+
+    fn accounts(&self) -> Box<dyn Iterator<Item = (&AccountId, &Account)> + '_> {
+        Box::new(self.iter())
+    }
+
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+}
+
+impl AccountStore for BTreeMap<AccountId, Account> {
+    fn account(&self, account_id: &AccountId) -> Option<&Account> {
+        self.get(account_id)
+    }
+
+    fn account_mut(&mut self, account_id: &AccountId) -> Option<&mut Account> {
+        self.get_mut(account_id)
+    }
+
+    fn open_account(&mut self, account_id: AccountId) -> &mut Account {
+        self.entry(account_id).or_default()
+    }
+
+    fn insert_account(&mut self, account_id: AccountId, account: Account) {
+        self.insert(account_id, account);
+    }
+
+    fn remove_account(&mut self, account_id: &AccountId) {
+        self.remove(account_id);
+    }
+
+    fn accounts(&self) -> Box<dyn Iterator<Item = (&AccountId, &Account)> + '_> {
+        Box::new(self.iter())
+    }
+
+    fn len(&self) -> usize {
+        BTreeMap::len(self)
+    }
+}
+
+// Map backing the accounts of the command line tool, chosen with --accounts-store
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) enum StoreKind {
+    #[default]
+    Hash,
+    BTree,
+}
+
+impl StoreKind {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "hash" => Some(StoreKind::Hash),
+            "btree" => Some(StoreKind::BTree),
+            _ => None,
+        }
+    }
+
+    pub fn new_store(self) -> Box<dyn AccountStore> {
+        match self {
+            StoreKind::Hash => Box::new(HashMap::new()),
+            StoreKind::BTree => Box::new(BTreeMap::new()),
+        }
+    }
+}
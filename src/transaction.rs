@@ -1,43 +1,167 @@
-use crate::account::AccountId;
-use crate::transaction::TransactionType::{Chargeback, Deposit, Dispute, Resolve, Withdrawal};
+use crate::account::{AccountId, ClientName};
+use crate::transaction::TransactionType::{
+    AccrueHeldInterest, Chargeback, Deposit, Dispute, ReleaseReserve, Resolve, Withdrawal,
+};
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display, Formatter};
 use DepositState::Deposited;
-use RowParsingError::{NegativeAmount, UndefinedAmount, UnknownTransactionType};
+use RowParsingError::{
+    InvalidClientName, NegativeAmount, UndefinedAmount, UnexpectedAmount, UnknownTransactionType,
+};
 
-#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
-pub(crate) struct TransactionId(u32);
+#[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub struct TransactionId(pub u32);
+
+impl Display for TransactionId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+// Identifies the rows applied atomically, which are consecutive rows sharing the same batch id
+#[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub struct BatchId(pub u32);
+
+impl Display for BatchId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
 
 // This struct defines all the fields we can find in the parsed CSV
 #[derive(Debug, Serialize, Deserialize)]
-struct TransactionRow {
+pub(crate) struct TransactionRow {
     #[serde(rename = "type")]
-    transaction_type: String,
-    #[serde(rename = "client")]
-    account_id: AccountId,
+    pub transaction_type: String,
+    #[serde(rename = "client", deserialize_with = "numeric_account_id")]
+    pub account_id: AccountId,
     #[serde(rename = "tx")]
-    transaction_id: TransactionId,
-    amount: Option<Decimal>,
+    pub transaction_id: TransactionId,
+    // An empty field, a blank one (fields are trimmed) and a missing trailing column all deserialize to None,
+    // so every form of missing amount surfaces as the same UndefinedAmount error for the types which require one
+    #[serde(default, deserialize_with = "exact_amount")]
+    pub amount: Option<Decimal>,
+    // Optional column, rows without a batch id are applied on their own
+    #[serde(default)]
+    pub batch: Option<BatchId>,
+}
+
+// Same as TransactionRow, for inputs whose client ids are kept as strings
+#[derive(Debug, Deserialize)]
+pub(crate) struct NamedTransactionRow {
+    #[serde(rename = "type")]
+    pub transaction_type: String,
+    pub client: String,
+    #[serde(rename = "tx")]
+    pub transaction_id: TransactionId,
+    #[serde(default, deserialize_with = "exact_amount")]
+    pub amount: Option<Decimal>,
+    #[serde(default)]
+    pub batch: Option<BatchId>,
+}
+
+// Without --string-client-ids the client column only holds numeric ids, names go through
+// NamedTransaction
+fn numeric_account_id<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<AccountId, D::Error> {
+    u16::deserialize(deserializer).map(AccountId::Number)
+}
+
+// Amounts are parsed from the text of the field. Deserializing a Decimal directly lets the CSV
+// reader infer a float for any amount looking like one, which rounds it to 17 significant digits.
+// Trailing zeros are dropped, so `3.0` is read as `3` like it was through the float
+fn exact_amount<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Decimal>, D::Error> {
+    match Option::<String>::deserialize(deserializer)? {
+        Some(amount) => parse_amount(&amount).map(Some),
+        None => Ok(None),
+    }
+}
+
+// Parses the text of an amount without rounding it, amounts with more digits than a decimal holds
+// being rejected
+pub(crate) fn parse_amount<E: serde::de::Error>(amount: &str) -> Result<Decimal, E> {
+    Decimal::from_str_exact(amount)
+        .or_else(|_| Decimal::from_scientific(amount))
+        .map(|amount| amount.normalize())
+        .map_err(|_| E::invalid_value(serde::de::Unexpected::Str(amount), &"an amount"))
 }
 
 // TransactionRow is converted into Transaction, which only contains fields available in every transaction type
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 #[serde(try_from = "TransactionRow")]
-pub(crate) struct Transaction {
-    pub(crate) transaction_type: TransactionType,
+pub struct Transaction {
+    pub transaction_type: TransactionType,
     pub account_id: AccountId,
     pub transaction_id: TransactionId,
+    pub batch: Option<BatchId>,
 }
 
+impl Transaction {
+    // A transaction applied on its own, outside of any batch
+    pub fn new(
+        transaction_type: TransactionType,
+        account_id: AccountId,
+        transaction_id: TransactionId,
+    ) -> Self {
+        Transaction {
+            transaction_type,
+            account_id,
+            transaction_id,
+            batch: None,
+        }
+    }
+}
+
+// A transaction read with --string-client-ids, whose client is identified by its original text
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(try_from = "NamedTransactionRow")]
+pub(crate) struct NamedTransaction(pub Transaction);
+
 #[derive(Copy, Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
-pub(crate) enum TransactionType {
+pub enum TransactionType {
     Deposit(Decimal, DepositState),
-    Withdrawal(Decimal),
+    Withdrawal(Decimal, DepositState),
     Dispute,
     Resolve,
     Chargeback,
+    // Moves funds previously reserved from deposits back from held to available
+    ReleaseReserve(Decimal),
+    // Credits interest on the held funds of the account, at the rate configured in the ledger
+    AccrueHeldInterest,
+}
+
+// Names of every transaction type, and of the ones which require an amount
+pub(crate) const TRANSACTION_TYPES: [&str; 7] = [
+    "deposit",
+    "withdrawal",
+    "dispute",
+    "resolve",
+    "chargeback",
+    "release_reserve",
+    "accrue_held_interest",
+];
+pub(crate) const AMOUNT_TYPES: [&str; 3] = ["deposit", "withdrawal", "release_reserve"];
+
+impl TransactionType {
+    // Name of the transaction type as it appears in the CSV input
+    pub fn name(&self) -> &'static str {
+        match self {
+            Deposit(..) => "deposit",
+            Withdrawal(..) => "withdrawal",
+            Dispute => "dispute",
+            Resolve => "resolve",
+            Chargeback => "chargeback",
+            ReleaseReserve(_) => "release_reserve",
+            AccrueHeldInterest => "accrue_held_interest",
+        }
+    }
 }
 
+// Dispute lifecycle of a stored deposit or withdrawal, which starts as Deposited once applied
 #[derive(Copy, Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
 pub enum DepositState {
     Deposited,
@@ -47,32 +171,200 @@ pub enum DepositState {
 }
 
 #[derive(thiserror::Error, Debug, Serialize, Deserialize)]
-pub(crate) enum TransactionFailure {
+pub enum TransactionFailure {
     #[error("{1:?} for {0:?} can't withdraw ${2} due to insufficient funds")]
     InsufficientFunds(AccountId, TransactionId, Decimal),
     #[error("{0:?} not found")]
     NonExistentTransaction(TransactionId),
     #[error("{0:?} not found")]
     NonExistentAccount(AccountId),
+    #[error("{0:?} already exists")]
+    DuplicateTransaction(TransactionId),
+    #[error("Transaction #{0} id reused with different type")]
+    ReusedTransactionType(TransactionId),
+    #[error(
+        "{0:?} was already deposited with amount {1} and cannot be redelivered with amount {2}"
+    )]
+    ConflictingDuplicate(TransactionId, Decimal, Decimal),
     #[error("{0:?} cannot transition from {1:?} to {2:?}")]
     InvalidDepositTransition(TransactionId, DepositState, DepositState),
+    #[error("Transaction #{0} is not under dispute")]
+    NotUnderDispute(TransactionId),
     // An invalid transaction reference happens if you attempt to dispute/resolve/chargeback a non-deposit transaction
     #[error("{1:?} cannot reference {0:?} which is a {2:?}")]
     InvalidTransactionReference(TransactionId, TransactionType, TransactionType),
+    #[error("Transaction #{0} is a withdrawal and cannot be disputed")]
+    UndisputableWithdrawal(TransactionId),
+    #[error("Transaction #{0} would exceed held cap for account #{1}")]
+    HeldCapExceeded(TransactionId, AccountId),
+    #[error(
+        "Transaction #{0} would push the held funds of account #{1} over {2} times its available funds"
+    )]
+    HeldRatioExceeded(TransactionId, AccountId, Decimal),
+    #[error("Transaction #{0} deposits ${1} which exceeds the maximum deposit of ${2}")]
+    DepositCapExceeded(TransactionId, Decimal, Decimal),
+    #[error("Transaction #{0} withdraws ${1} which exceeds the maximum withdrawal of ${2}")]
+    WithdrawalCapExceeded(TransactionId, Decimal, Decimal),
+    #[error("Transaction #{0} releases ${1} but account #{2} only has ${3} reserved")]
+    ReserveReleaseExceeded(TransactionId, Decimal, AccountId, Decimal),
+    #[error("Transaction #{0} accrues interest on held funds but no interest rate is configured")]
+    HeldInterestNotConfigured(TransactionId),
+    #[error("Transaction #{0} does not belong to account #{1}")]
+    ForeignTransactionReference(TransactionId, AccountId),
+    #[error("Transaction #{0} blocked by deposit cooldown")]
+    BlockedByCooldown(TransactionId),
+    #[error(
+        "Transaction #{0} amount {1} has more than {2} decimal places, which looks like a float serialization artifact"
+    )]
+    FloatArtifact(TransactionId, Decimal, u32),
+    #[error("Transaction #{0} for account #{1} overflowed account balance")]
+    BalanceOverflowed(TransactionId, AccountId),
+    #[error("Account #{0} is locked and cannot process transaction #{1}")]
+    LockedAccount(AccountId, TransactionId),
+    #[error("Batch #{0} was rolled back after applying {1} of its transactions")]
+    BatchRolledBack(BatchId, usize),
+    #[error("Transaction #{0} was skipped because batch #{1} was rolled back")]
+    SkippedBatchTransaction(TransactionId, BatchId),
+}
+
+impl TransactionFailure {
+    // Stable identifier of the failure, for consumers which group errors without parsing messages
+    pub fn kind(&self) -> &'static str {
+        use TransactionFailure::*;
+        match self {
+            InsufficientFunds(..) => "insufficient_funds",
+            NonExistentTransaction(_) => "non_existent_transaction",
+            NonExistentAccount(_) => "non_existent_account",
+            ForeignTransactionReference(..) => "foreign_transaction_reference",
+            LockedAccount(..) => "locked_account",
+            BalanceOverflowed(..) => "balance_overflow",
+            FloatArtifact(..) => "float_artifact",
+            BlockedByCooldown(_) => "deposit_cooldown",
+            BatchRolledBack(..) => "batch_rolled_back",
+            SkippedBatchTransaction(..) => "skipped_batch_transaction",
+            DuplicateTransaction(_) => "duplicate_transaction",
+            ReusedTransactionType(_) => "reused_transaction_type",
+            ConflictingDuplicate(..) => "conflicting_duplicate",
+            InvalidDepositTransition(..) => "invalid_deposit_transition",
+            NotUnderDispute(_) => "not_under_dispute",
+            InvalidTransactionReference(..) => "invalid_transaction_reference",
+            UndisputableWithdrawal(_) => "undisputable_withdrawal",
+            HeldCapExceeded(..) => "held_cap_exceeded",
+            HeldRatioExceeded(..) => "held_ratio_exceeded",
+            DepositCapExceeded(..) => "deposit_cap_exceeded",
+            WithdrawalCapExceeded(..) => "withdrawal_cap_exceeded",
+            ReserveReleaseExceeded(..) => "reserve_release_exceeded",
+            HeldInterestNotConfigured(_) => "held_interest_not_configured",
+        }
+    }
 }
 
 // The result of a transaction is either an empty type, meaning the transaction completed successfully,
 // or a particular transaction failure enum
-pub(crate) type TransactionResult = Result<(), TransactionFailure>;
+pub type TransactionResult = Result<(), TransactionFailure>;
+
+// What an accepted transaction did to the ledger. Redelivered deposits merged into their original
+// are accepted without changing any state
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Outcome {
+    Applied,
+    Ignored,
+}
 
 #[derive(thiserror::Error, Debug, Serialize, Deserialize)]
-enum RowParsingError {
+pub(crate) enum RowParsingError {
     #[error("{0} is an unknown type")]
     UnknownTransactionType(String),
     #[error("Transaction requires a defined amount")]
     UndefinedAmount,
     #[error("Transaction requires a positive amount but was {0}")]
     NegativeAmount(Decimal),
+    #[error("Transaction #{0} of type {1} must not specify an amount")]
+    UnexpectedAmount(TransactionId, &'static str),
+    #[error("{0:?} is not a valid client id, which must not be blank")]
+    InvalidClientName(String),
+}
+
+// A row whose number of fields doesn't fit its transaction type, which would otherwise have its
+// extra fields ignored or its columns shifted by the flexible reader
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum FieldCountMismatch {
+    #[error("Line {line} has {fields} fields but a {transaction_type} takes at most {max}")]
+    TooMany {
+        line: u64,
+        transaction_type: String,
+        fields: usize,
+        max: usize,
+    },
+    #[error("Line {line} has {fields} fields but a {transaction_type} takes at least {min}")]
+    TooFew {
+        line: u64,
+        transaction_type: String,
+        fields: usize,
+        min: usize,
+    },
+}
+
+// Checks the number of fields of a row against the columns its type uses. Fields past the last
+// column must be empty, as left by trailing delimiters, and every column up to the last one the
+// type requires must be present.
+// A missing amount is left for the deserializer, so it's reported as an UndefinedAmount whether
+// the field is empty or the trailing column is missing altogether
+pub(crate) fn check_field_count(
+    record: &csv::StringRecord,
+    headers: &csv::StringRecord,
+) -> Result<(), FieldCountMismatch> {
+    let column = |name: &str| headers.iter().position(|header| header == name);
+    let transaction_type = match column("type").and_then(|index| record.get(index)) {
+        Some(transaction_type) => transaction_type,
+        // Rows without a type fail to deserialize on their own
+        None => return Ok(()),
+    };
+    if !TRANSACTION_TYPES.contains(&transaction_type) {
+        return Ok(());
+    }
+
+    let line = record.position().map_or(0, |position| position.line());
+    // Trailing delimiters only add empty fields, which don't count
+    let fields = record.len()
+        - record
+            .iter()
+            .rev()
+            .take_while(|field| field.is_empty())
+            .count()
+            .min(record.len().saturating_sub(headers.len()));
+    let max = headers.len();
+    let min = ["type", "client", "tx"]
+        .iter()
+        .filter_map(|name| column(name))
+        .max()
+        .map_or(0, |index| index + 1);
+    if fields > max {
+        Err(FieldCountMismatch::TooMany {
+            line,
+            transaction_type: transaction_type.to_string(),
+            fields,
+            max,
+        })
+    } else if fields < min {
+        Err(FieldCountMismatch::TooFew {
+            line,
+            transaction_type: transaction_type.to_string(),
+            fields,
+            min,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+// Amount of the types listed in AMOUNT_TYPES, which must be present and positive
+fn required_amount(amount: Option<Decimal>) -> Result<Decimal, RowParsingError> {
+    match amount {
+        None => Err(UndefinedAmount),
+        Some(amount) if amount.is_sign_negative() => Err(NegativeAmount(amount)),
+        Some(amount) => Ok(amount),
+    }
 }
 
 impl TryFrom<TransactionRow> for Transaction {
@@ -84,28 +376,338 @@ impl TryFrom<TransactionRow> for Transaction {
             account_id,
             transaction_id,
             amount,
+            batch,
         } = row;
 
-        let sane_amount = amount.ok_or(UndefinedAmount).and_then(|value| {
-            if value.is_sign_negative() {
-                Err(NegativeAmount(value))
-            } else {
-                Ok(value)
-            }
-        });
-
         let transaction_type = match transaction_type.as_str() {
-            "deposit" => Deposit(sane_amount?, Deposited),
-            "withdrawal" => Withdrawal(sane_amount?),
+            "deposit" => Deposit(required_amount(amount)?, Deposited),
+            "withdrawal" => Withdrawal(required_amount(amount)?, Deposited),
             "dispute" => Dispute,
             "resolve" => Resolve,
             "chargeback" => Chargeback,
+            "release_reserve" => ReleaseReserve(required_amount(amount)?),
+            "accrue_held_interest" => AccrueHeldInterest,
             unknown_type => return Err(UnknownTransactionType(unknown_type.to_string())),
         };
+        // The types which don't take an amount reject one instead of silently ignoring it, while an
+        // empty amount field left by a trailing delimiter is the same as a row without the column
+        if amount.is_some() && !AMOUNT_TYPES.contains(&transaction_type.name()) {
+            return Err(UnexpectedAmount(transaction_id, transaction_type.name()));
+        }
         Ok(Transaction {
             transaction_type,
             transaction_id,
             account_id,
+            batch,
+        })
+    }
+}
+
+impl TryFrom<NamedTransactionRow> for NamedTransaction {
+    type Error = RowParsingError;
+
+    fn try_from(row: NamedTransactionRow) -> Result<Self, Self::Error> {
+        let NamedTransactionRow {
+            transaction_type,
+            client,
+            transaction_id,
+            amount,
+            batch,
+        } = row;
+        let name = match ClientName::new(client.trim()) {
+            Some(name) => name,
+            None => return Err(InvalidClientName(client)),
+        };
+        Transaction::try_from(TransactionRow {
+            transaction_type,
+            account_id: AccountId::Name(name),
+            transaction_id,
+            amount,
+            batch,
         })
+        .map(NamedTransaction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::jsonl::jsonl_rows;
+    use crate::ledger::Ledger;
+    use crate::processing::tests::process_csv;
+    use crate::processing::{process_rows, Processing};
+    use crate::transaction::TransactionType;
+    use crate::{transactions, AccountId, Transaction};
+    use csv::{ReaderBuilder, Trim};
+    use rust_decimal::Decimal;
+    use std::collections::HashMap;
+
+    #[test]
+    fn validates_the_field_count_of_deposits() {
+        let csv = "type,client,tx,amount
+deposit,1,1,5
+deposit,1,2
+deposit,1,3,
+deposit,1,4,5,
+deposit,1,5,5,,
+deposit,1,6,,5
+deposit,1,7,5,6
+deposit,1,8,,
+deposit,1";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let (accounts, errors) = process_csv(csv);
+        // Only the complete rows and the ones with trailing delimiters are applied
+        let first_account = accounts.get(&AccountId::Number(1)).unwrap();
+        assert_eq!(first_account.total(), Decimal::from(15));
+
+        let errors = errors
+            .iter()
+            .map(|error| error.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(errors.len(), 6);
+        // A missing amount is reported the same way whether the column is empty or missing
+        assert_eq!(
+            errors[0],
+            "CSV deserialize error: record 2 (line: 3, byte: 36): Transaction requires a defined amount"
+        );
+        assert_eq!(
+            errors[1],
+            "CSV deserialize error: record 3 (line: 4, byte: 48): Transaction requires a defined amount"
+        );
+        // An amount shifted into an extra column isn't mistaken for a missing amount
+        assert_eq!(
+            errors[2],
+            "Line 7 has 5 fields but a deposit takes at most 4"
+        );
+        assert_eq!(
+            errors[3],
+            "Line 8 has 5 fields but a deposit takes at most 4"
+        );
+        assert_eq!(
+            errors[4],
+            "CSV deserialize error: record 8 (line: 9, byte: 123): Transaction requires a defined amount"
+        );
+        assert_eq!(
+            errors[5],
+            "Line 10 has 2 fields but a deposit takes at least 3"
+        );
+    }
+
+    #[test]
+    fn reports_every_form_of_missing_amount_identically() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,
+                        deposit,1,2,   
+                        deposit,1,3
+                        withdrawal,1,4,
+                        withdrawal,1,5,   
+                        withdrawal,1,6";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let (accounts, errors) = process_csv(csv);
+        assert!(accounts.is_empty());
+        assert_eq!(errors.len(), 6);
+        for error in errors {
+            assert!(error
+                .to_string()
+                .ends_with("): Transaction requires a defined amount"));
+        }
+    }
+
+    #[test]
+    fn keeps_client_ids_as_text_when_requested() {
+        let csv = "type,client,tx,amount
+                        deposit, 01, 1, 10
+                        deposit, 1, 2, 5
+                        dispute, 1, 1,";
+        let reader = || {
+            ReaderBuilder::new()
+                .has_headers(true)
+                .trim(Trim::All)
+                .flexible(true)
+                .from_reader(csv.as_bytes())
+        };
+
+        // Numerically both clients are the same account, which can dispute either deposit
+        let mut accounts = HashMap::new();
+        let errors = process_rows(
+            &mut Ledger::default(),
+            &mut accounts,
+            transactions(reader(), false),
+            &mut Processing::default(),
+        );
+        assert_eq!(errors.len(), 0);
+        assert_eq!(accounts.len(), 1);
+        let account = accounts.get(&AccountId::Number(1)).unwrap();
+        assert_eq!(account.held(), Decimal::TEN);
+        assert_eq!(account.total(), Decimal::from(15));
+
+        let mut accounts = HashMap::new();
+        let errors = process_rows(
+            &mut Ledger::default(),
+            &mut accounts,
+            transactions(reader(), true),
+            &mut Processing::default(),
+        );
+        assert_eq!(accounts.len(), 2);
+        let padded = accounts
+            .get(&AccountId::parse("01", true).unwrap())
+            .unwrap();
+        let plain = accounts.get(&AccountId::parse("1", true).unwrap()).unwrap();
+        assert_eq!(padded.total(), Decimal::TEN);
+        assert_eq!(plain.total(), Decimal::from(5));
+        assert_eq!(plain.held(), Decimal::ZERO);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "Transaction #1 does not belong to account #1"
+        );
+    }
+
+    #[test]
+    fn accepts_client_names_of_any_length() {
+        let name = "3f2b8c1e-6d4a-4f0e-9b7a-2c5d8e1f4a6b/settlement";
+        let csv = format!(
+            "type,client,tx,amount
+                        deposit, {0}, 1, 10
+                        dispute, {0}, 1,
+                        deposit, , 2, 5",
+            name
+        );
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let mut accounts = HashMap::new();
+        let errors = process_rows(
+            &mut Ledger::default(),
+            &mut accounts,
+            transactions(csv, true),
+            &mut Processing::default(),
+        );
+        let account = &accounts[&AccountId::parse(name, true).unwrap()];
+        assert_eq!(account.held(), Decimal::TEN);
+        assert_eq!(
+            errors.iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+            ["CSV deserialize error: record 3 (line: 4, byte: 195): \"\" is not a valid client id, which must not be blank"]
+        );
+        assert_eq!(AccountId::parse(name, true).unwrap().to_string(), name);
+    }
+
+    #[test]
+    fn reads_amounts_without_losing_precision() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,1.123456789012345678901234567
+                        deposit,1,2,0.1000000000000000000000000001
+                        deposit,1,3,1.12345678901234567890123456789";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+        let jsonl = r#"{"type": "deposit", "client": 2, "tx": 4, "amount": 1.123456789012345678901234567}
+            {"type": "deposit", "client": 2, "tx": 5, "amount": "0.1000000000000000000000000001"}
+            {"type": "deposit", "client": 2, "tx": 6, "amount": 2.5e-3}"#;
+
+        let amounts = transactions(csv, false)
+            .map(|row| row.map_err(|error| error.to_string()))
+            .chain(
+                jsonl_rows(jsonl.as_bytes(), false)
+                    .map(|row| row.row.map_err(|error| error.to_string())),
+            )
+            .map(|row| match row?.transaction_type {
+                TransactionType::Deposit(amount, _) => Ok::<_, String>(amount.to_string()),
+                other => panic!("{:?} is not a deposit", other),
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(amounts[0].as_deref(), Ok("1.123456789012345678901234567"));
+        assert_eq!(amounts[1].as_deref(), Ok("0.1000000000000000000000000001"));
+        // Amounts with more digits than a decimal holds are rejected instead of being rounded
+        assert!(amounts[2].as_ref().unwrap_err().contains(
+            r#"invalid value: string "1.12345678901234567890123456789", expected an amount"#
+        ));
+        assert_eq!(amounts[3].as_deref(), Ok("1.123456789012345678901234567"));
+        assert_eq!(amounts[4].as_deref(), Ok("0.1000000000000000000000000001"));
+        assert_eq!(amounts[5].as_deref(), Ok("0.0025"));
+    }
+
+    #[test]
+    fn only_requires_amounts_from_the_types_which_take_one() {
+        let csv = "type,client,tx,amount
+                        dispute,1,1,
+                        dispute,1,1
+                        resolve,1,1,
+                        chargeback,1,1,
+                        deposit,1,2,";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let rows = transactions(csv, false).collect::<Vec<_>>();
+        let parsed = |index: usize| {
+            let transaction: &Transaction = rows[index].as_ref().unwrap();
+            (
+                transaction.transaction_type,
+                transaction.account_id,
+                transaction.transaction_id,
+            )
+        };
+
+        // An empty amount and a missing one parse to the same dispute
+        assert_eq!(parsed(0), parsed(1));
+        assert_eq!(parsed(0).0, TransactionType::Dispute);
+        assert_eq!(parsed(2).0, TransactionType::Resolve);
+        assert_eq!(parsed(3).0, TransactionType::Chargeback);
+        assert!(rows[4]
+            .as_ref()
+            .unwrap_err()
+            .to_string()
+            .ends_with("): Transaction requires a defined amount"));
+    }
+
+    #[test]
+    fn rejects_amounts_on_the_types_which_take_none() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,10
+                        dispute,1,1,5.0
+                        dispute,1,1,
+                        resolve,1,1,1
+                        chargeback,1,1,1";
+        let csv = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+
+        let (accounts, errors) = process_csv(csv);
+        let first_account = accounts.get(&AccountId::Number(1)).unwrap();
+
+        // Only the dispute without an amount is applied
+        assert_eq!(first_account.held(), Decimal::TEN);
+        assert!(!first_account.locked());
+        assert_eq!(errors.len(), 3);
+        assert!(errors[0]
+            .to_string()
+            .ends_with("): Transaction #1 of type dispute must not specify an amount"));
+        assert!(errors[1]
+            .to_string()
+            .ends_with("): Transaction #1 of type resolve must not specify an amount"));
+        assert!(errors[2]
+            .to_string()
+            .ends_with("): Transaction #1 of type chargeback must not specify an amount"));
     }
 }
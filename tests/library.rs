@@ -0,0 +1,189 @@
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use transaction_processor::transaction::DepositState::Deposited;
+use transaction_processor::transaction::TransactionType::{
+    Chargeback, Deposit, Dispute, Resolve, Withdrawal,
+};
+use transaction_processor::transaction::{TransactionId, TransactionType};
+use transaction_processor::{
+    process_reader, process_reader_into, process_reader_parallel, process_report, AccountId,
+    Ledger, Options, ReportStats, Transaction,
+};
+
+// Disputes the second of two deposits, returns the account total after the deposits, the dispute
+// and the specified settlement of the dispute
+fn totals_across_dispute(settlement: TransactionType) -> [Decimal; 3] {
+    let mut ledger = Ledger::default();
+    let mut accounts = HashMap::new();
+    let client = AccountId::Number(1);
+    let mut apply = |transaction: Transaction| {
+        ledger
+            .process_transaction(&mut accounts, transaction)
+            .unwrap();
+        accounts[&client].total()
+    };
+    apply(Transaction::new(
+        Deposit(Decimal::TEN, Deposited),
+        client,
+        TransactionId(1),
+    ));
+    [
+        apply(Transaction::new(
+            Deposit(Decimal::from_str_exact("2.5").unwrap(), Deposited),
+            client,
+            TransactionId(2),
+        )),
+        apply(Transaction::new(Dispute, client, TransactionId(2))),
+        apply(Transaction::new(settlement, client, TransactionId(2))),
+    ]
+}
+
+#[test]
+fn applies_transactions_without_going_through_csv() {
+    let mut ledger = Ledger::default();
+    let mut accounts = HashMap::new();
+    let client = AccountId::Number(1);
+    for transaction in [
+        Transaction::new(Deposit(Decimal::TEN, Deposited), client, TransactionId(1)),
+        Transaction::new(Deposit(Decimal::TWO, Deposited), client, TransactionId(2)),
+        Transaction::new(
+            Withdrawal(Decimal::ONE, Deposited),
+            client,
+            TransactionId(3),
+        ),
+        Transaction::new(Dispute, client, TransactionId(2)),
+    ] {
+        ledger
+            .process_transaction(&mut accounts, transaction)
+            .unwrap();
+    }
+
+    let account = &accounts[&client];
+    assert_eq!(account.available(), Decimal::from(9));
+    assert_eq!(account.held(), Decimal::TWO);
+    assert_eq!(account.total(), Decimal::from(11));
+}
+
+#[test]
+fn processes_any_reader() {
+    let (accounts, errors) = process_reader(File::open("tests/basic.csv").unwrap());
+    assert_eq!(errors.len(), 1);
+    assert_eq!(
+        accounts[&AccountId::Number(1)].available(),
+        Decimal::from_str_exact("1.5").unwrap()
+    );
+    assert_eq!(accounts[&AccountId::Number(2)].total(), Decimal::TWO);
+}
+
+#[test]
+fn keeps_the_total_across_dispute_and_resolve() {
+    let total = Decimal::from_str_exact("12.5").unwrap();
+    assert_eq!(totals_across_dispute(Resolve), [total; 3]);
+}
+
+#[test]
+fn reduces_the_total_by_the_charged_back_amount() {
+    let total = Decimal::from_str_exact("12.5").unwrap();
+    assert_eq!(
+        totals_across_dispute(Chargeback),
+        [total, total, Decimal::TEN]
+    );
+}
+
+#[test]
+fn processes_into_either_accounts_store() {
+    let input = "type,client,tx,amount
+                 deposit,3,1,3
+                 deposit,1,2,1.5
+                 deposit,2,3,2
+                 withdrawal,3,4,0.5
+                 dispute,1,2,
+                 deposit,2,5,0.25";
+    let (hashed, hashed_errors) = process_reader(input.as_bytes());
+    let (ordered, ordered_errors) = process_reader_into::<BTreeMap<AccountId, _>>(input.as_bytes());
+
+    assert_eq!(hashed_errors.len(), ordered_errors.len());
+    assert_eq!(
+        hashed,
+        ordered.clone().into_iter().collect::<HashMap<_, _>>()
+    );
+    // The BTreeMap lists the accounts by client
+    assert_eq!(
+        ordered.keys().copied().collect::<Vec<_>>(),
+        [1, 2, 3].map(AccountId::Number)
+    );
+}
+
+#[test]
+fn processes_clients_in_parallel() {
+    let (accounts, errors) = process_reader(File::open("tests/basic.csv").unwrap());
+    let (parallel_accounts, parallel_errors) =
+        process_reader_parallel(File::open("tests/basic.csv").unwrap());
+    assert_eq!(parallel_accounts, accounts);
+    assert_eq!(parallel_errors.len(), errors.len());
+}
+
+#[test]
+fn returns_a_structured_report() {
+    let report = process_report(File::open("tests/basic.csv").unwrap());
+
+    let mut rendered = vec![];
+    report
+        .write_csv(&mut rendered, &Options::default())
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(rendered).unwrap(),
+        "client,available,held,total,locked\n\
+         1,1.5000,0.0000,1.5000,false\n\
+         2,2.0000,0.0000,2.0000,false\n"
+    );
+    // Rendered with the options of the command line flags, like the tool would
+    let args = [
+        "transaction_processor",
+        "--precision",
+        "total=2",
+        "--client-width",
+        "3",
+    ]
+    .map(String::from);
+    let mut rendered = vec![];
+    report
+        .write_csv(&mut rendered, &Options::parse(&args).unwrap())
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(rendered).unwrap(),
+        "client,available,held,total,locked\n\
+         001,1.5000,0.0000,1.50,false\n\
+         002,2.0000,0.0000,2.00,false\n"
+    );
+    assert_eq!(
+        report
+            .accounts
+            .iter()
+            .map(|(client, account)| (*client, account.total()))
+            .collect::<Vec<_>>(),
+        [
+            (
+                AccountId::Number(1),
+                Decimal::from_str_exact("1.5").unwrap()
+            ),
+            (AccountId::Number(2), Decimal::TWO),
+        ]
+    );
+    assert_eq!(
+        report
+            .errors
+            .iter()
+            .map(|error| (error.line, error.kind, error.transaction))
+            .collect::<Vec<_>>(),
+        [(6, "insufficient_funds", Some(TransactionId(5)))]
+    );
+    assert_eq!(
+        report.stats,
+        ReportStats {
+            rows_read: 5,
+            transactions_applied: 4,
+        }
+    );
+}
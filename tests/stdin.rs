@@ -0,0 +1,45 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+#[test]
+fn reads_the_input_from_stdin_without_a_path() {
+    let mut processor = Command::new(env!("CARGO_BIN_EXE_transaction_processor"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    processor
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"type, client, tx, amount\ndeposit, 1, 1, 2\nwithdrawal, 1, 2, 0.5\n")
+        .unwrap();
+    let output = processor.wait_with_output().unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,1.5000,0.0000,1.5000,false\n"
+    );
+}
+
+#[test]
+fn processes_several_inputs_in_order() {
+    let output = Command::new(env!("CARGO_BIN_EXE_transaction_processor"))
+        .args(["tests/hour1.csv", "tests/hour2.csv", "--diagnostics"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    // The second input disputes a deposit of the first one
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n\
+         1,0.0000,3.0000,3.0000,false\n\
+         2,1.5000,0.0000,1.5000,false\n"
+    );
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "line 3: [ERROR] tests/hour2.csv: TransactionId(5) not found\n"
+    );
+}